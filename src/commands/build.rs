@@ -1,63 +1,185 @@
-use std::{env::current_dir, process::Command};
+use std::{
+    env::current_dir,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Instant,
+};
 
 use anyhow::Result;
 use color_print::cprintln;
 
-use crate::{config::read_bin_config, CommandAndHandler};
+use crate::{
+    config::{read_bin_config, BinaryConfig},
+    diagnostics::Diagnostics,
+    error::KbuildError,
+    report::{crate_timings, BuildReport},
+    utils::{format_command, get_positional, has_flag, spawn},
+    CommandAndHandler,
+};
 
-/// The handler of the command.
-fn handler(args: Vec<String>) -> Result<()> {
-    let file_name = match args.len() > 2 {
-        true => &args[2],
-        false => "default.toml",
-    };
+/// The options of the build.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Collect the compiler errors and warnings, print a summary at the end.
+    pub diagnostics: bool,
+    /// Print the diagnostics as json, implies `diagnostics`.
+    pub json: bool,
+    /// Print the build time and section sizes, compare with the previous build.
+    pub report: bool,
+    /// Print the cargo command line instead of running it.
+    pub dry_run: bool,
+}
 
-    let bin = if args.len() > 3 { &args[3] } else { "default" };
+impl BuildOptions {
+    pub fn from_args(args: &[String]) -> Self {
+        let json = has_flag(args, "--json");
+        BuildOptions {
+            diagnostics: json || has_flag(args, "--diagnostics"),
+            json,
+            report: has_flag(args, "--report"),
+            dry_run: has_flag(args, "--dry-run"),
+        }
+    }
+}
 
-    let mut rustflags = Vec::new();
-    // default configuration.
-    rustflags.push(String::from("-Cforce-frame-pointers=yes"));
-    rustflags.push(String::from("-Clink-arg=-no-pie"));
-    rustflags.push(String::from("-Ztls-model=local-exec"));
+/// Get the cargo command with the target, rustflags and envs of the bin.
+///
+/// `root` is passed to the build scripts as `ROOT_MANIFEST_DIR`.
+pub fn cargo_command(binary_config: &BinaryConfig, subcommand: &str, root: &Path) -> Command {
+    let mut command = Command::new("cargo");
+    command
+        .env("RUSTFLAGS", binary_config.get_rustflags().join(" "))
+        .env("ROOT_MANIFEST_DIR", root)
+        .env("CARGO_TARGET_DIR", binary_config.get_target_dir())
+        .envs(binary_config.get_envs())
+        .arg(subcommand);
+    if let Some(ref build_std) = binary_config.build_std {
+        command.arg("-Z").arg(format!("build-std={build_std}"));
+    }
+    command.arg("--target").arg(&binary_config.target);
+    command
+}
 
+/// Build the bin from the config file.
+pub fn build(file_name: &str, bin: &str, options: &BuildOptions) -> Result<()> {
     // Convert kernel configuration to rustflags.
     // This rustflags will be passed to the rust build command.
     let binary_config = read_bin_config(file_name, bin)?;
-    for (key, value) in binary_config.get_configs() {
-        rustflags.push(format!("--cfg={}=\"{}\"", key, value));
-        println!("{} = {:?}", key, value);
+    let target_dir = binary_config.get_target_dir();
+    // Keep stdout clean for the json output.
+    if !options.json {
+        for (key, value) in binary_config.get_configs() {
+            println!("{} = {:?}", key, value);
+        }
+        cprintln!(
+            "<green>config hash</green> {} -> {}",
+            binary_config.get_hash(),
+            target_dir.display()
+        );
+    }
+
+    if binary_config.build_std.is_some() && !options.json {
+        cprintln!("<green>build_std</green>");
     }
 
     let mut extra_args = Vec::new();
 
-    if let Some(ref build_std) = binary_config.build_std {
-        cprintln!("<green>build_std</green>");
-        extra_args.push(String::from("-Z"));
-        extra_args.push(format!("build-std={build_std}"));
+    // The json messages are needed to find the executable for the report.
+    let capture = options.diagnostics || options.report;
+    if options.report {
+        extra_args.push(String::from("--timings"));
+    }
+    if capture {
+        extra_args.push(String::from(match options.json {
+            true => "--message-format=json",
+            false => "--message-format=json-diagnostic-rendered-ansi",
+        }));
     }
 
-    // build os
-    let mut outputs = Command::new("cargo")
-        .env("RUSTFLAGS", rustflags.join(" "))
-        .env("ROOT_MANIFEST_DIR", current_dir().unwrap())
-        .envs(binary_config.get_envs())
-        .arg("build")
+    let mut command = cargo_command(&binary_config, "build", &current_dir()?);
+    command
         .args(extra_args)
-        .arg("--target")
-        .arg(binary_config.target)
         .arg("--release")
-        .spawn()
-        .expect("can't build kernel");
+        .stdout(match capture {
+            true => Stdio::piped(),
+            false => Stdio::inherit(),
+        });
+    if options.dry_run {
+        println!("{}", format_command(&command));
+        return Ok(());
+    }
+
+    // build os
+    let start = Instant::now();
+    let mut outputs = spawn(&mut command)?;
+
+    let mut diagnostics = Diagnostics::default();
+    let mut executable = None;
+    if let Some(stdout) = outputs.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let Some(msg) = diagnostics.handle_line(&line, options.json) else {
+                continue;
+            };
+            if msg["reason"] == "compiler-artifact" {
+                if let Some(path) = msg["executable"].as_str() {
+                    executable = Some(PathBuf::from(path));
+                }
+            }
+        }
+    }
 
     // Wait for build complete.
-    let exit_status = outputs.wait().expect("can't wait for build");
+    let exit_status = outputs.wait()?;
+    if options.json {
+        println!("{}", diagnostics.to_json().pretty(2));
+    } else if options.diagnostics {
+        diagnostics.print_summary();
+    }
     if !exit_status.success() {
-        return Err(anyhow!("build bin target {bin} failed, {exit_status}"));
+        return Err(KbuildError::BuildFailed {
+            bin: bin.to_string(),
+            status: exit_status.to_string(),
+            diagnostics: options.diagnostics,
+        }
+        .into());
+    }
+
+    if options.report {
+        let report = BuildReport::new(
+            binary_config.get_hash(),
+            start.elapsed().as_secs_f64(),
+            executable.as_deref(),
+        )?;
+        // The timing report is optional, the format of it isn't stable.
+        let timings = crate_timings(&target_dir).unwrap_or_else(|err| {
+            cprintln!(
+                "<yellow>warning: can't read the crate timings, {}</yellow>",
+                err
+            );
+            Vec::new()
+        });
+        report.print(bin, BuildReport::load(bin).as_ref(), &timings);
+        report.save(bin)?;
     }
 
     Ok(())
 }
 
+/// The handler of the command.
+fn handler(args: Vec<String>) -> Result<()> {
+    let options = BuildOptions::from_args(&args);
+    let args = get_positional(&args, &[]);
+    let file_name = match args.len() > 2 {
+        true => &args[2],
+        false => "default.toml",
+    };
+
+    let bin = if args.len() > 3 { &args[3] } else { "default" };
+
+    build(file_name, bin, &options)
+}
+
 inventory::submit! {
     CommandAndHandler::new("build", "build the kernel through a yaml.", handler)
 }
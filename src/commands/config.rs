@@ -2,7 +2,7 @@ use std::process::{Command, Stdio};
 
 use anyhow::Result;
 
-use crate::{config::read_bin_config, CommandAndHandler};
+use crate::{config::read_bin_config, utils::spawn, CommandAndHandler};
 
 /// The handler of the command.
 fn handler(args: Vec<String>) -> Result<()> {
@@ -34,12 +34,13 @@ fn handler(args: Vec<String>) -> Result<()> {
             .ok_or(anyhow!("Can't find config {name}")),
         "get_triple" => {
             // -Z unstable-options --print target-spec-json --target riscv64gc-unknown-none-elf
-            let task = Command::new("rustc")
-                .args(vec!["+nightly", "-Z", "unstable-options"])
-                .args(vec!["--print", "target-spec-json"])
-                .args(vec!["--target", &binary_config.target])
-                .stdout(Stdio::piped())
-                .spawn()?;
+            let task = spawn(
+                Command::new("rustc")
+                    .args(vec!["+nightly", "-Z", "unstable-options"])
+                    .args(vec!["--print", "target-spec-json"])
+                    .args(vec!["--target", &binary_config.target])
+                    .stdout(Stdio::piped()),
+            )?;
             let outputs = task.wait_with_output()?;
             let str = String::from_utf8(outputs.stdout)?;
             let triple = json::parse(&str)?;
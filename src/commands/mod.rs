@@ -4,5 +4,6 @@ mod help;
 mod patch;
 mod update_self;
 mod ver;
+mod watch;
 
 pub use help::handler as help_handler;
@@ -0,0 +1,163 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use toml::Table;
+
+use crate::utils::wildcard_match;
+
+/// Get the directory name of the git repository, e.g. `polyhal` for
+/// `https://github.com/Byte-OS/polyhal.git`.
+pub fn repo_name(git: &str) -> String {
+    let path = git.trim_end_matches('/');
+    let name = path.rsplit(['/', ':']).next().unwrap_or(path);
+    name.strip_suffix(".git").unwrap_or(name).to_string()
+}
+
+/// Get the path of the crate in the repository from the manifest path in the
+/// cargo checkout, e.g. `~/.cargo/git/checkouts/<repo>-<hash>/<commit>/<path>/Cargo.toml`.
+pub fn cargo_checkout_sub_path(manifest_path: &Path) -> Option<String> {
+    let components: Vec<_> = manifest_path.parent()?.components().collect();
+    // The repository may have a `checkouts` directory too.
+    let index = components
+        .windows(2)
+        .position(|x| x[0].as_os_str() == "git" && x[1].as_os_str() == "checkouts")?
+        + 1;
+    let sub_path: Vec<_> = components
+        .get(index + 3..)?
+        .iter()
+        .map(|x| x.as_os_str().to_string_lossy())
+        .collect();
+    Some(sub_path.join("/"))
+}
+
+/// Get the package name in the manifest.
+fn package_name(manifest: &Path) -> Option<String> {
+    let content: Table = fs::read_to_string(manifest).ok()?.parse().ok()?;
+    content
+        .get("package")?
+        .get("name")?
+        .as_str()
+        .map(String::from)
+}
+
+/// Expand the workspace member like `crates/*` to the directories.
+fn expand_member(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut res = vec![dir.to_path_buf()];
+    for segment in pattern.split('/').filter(|x| !x.is_empty() && *x != ".") {
+        if !segment.contains(['*', '?']) {
+            res = res.into_iter().map(|x| x.join(segment)).collect();
+            continue;
+        }
+        res = res
+            .iter()
+            .filter_map(|x| fs::read_dir(x).ok())
+            .flat_map(|entries| entries.flatten())
+            .filter(|entry| wildcard_match(segment, &entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .collect();
+    }
+    res
+}
+
+/// Collect every `Cargo.toml` in the dir, `target` and hidden dirs are skipped.
+pub fn collect_manifests(dir: &Path, manifests: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let path = entry.path();
+        if path.is_dir() && !name.starts_with('.') && name != "target" {
+            collect_manifests(&path, manifests);
+        } else if name == "Cargo.toml" {
+            manifests.push(path);
+        }
+    }
+}
+
+/// Find the path of the crate in the git checkout, relative to the checkout.
+///
+/// The root package and the workspace members are checked first, other
+/// manifests in the repository are searched like cargo does for git dependencies.
+pub fn find_crate_path(dir: &Path, name: &str) -> Result<String> {
+    let root_manifest: Option<Table> = fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|x| x.parse().ok());
+    let members: Vec<String> = root_manifest
+        .as_ref()
+        .and_then(|x| x.get("workspace")?.get("members")?.as_array().cloned())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|x| x.as_str().map(String::from))
+        .collect();
+
+    let mut manifests = vec![dir.join("Cargo.toml")];
+    for member in members {
+        manifests.extend(
+            expand_member(dir, &member)
+                .into_iter()
+                .map(|x| x.join("Cargo.toml")),
+        );
+    }
+    collect_manifests(dir, &mut manifests);
+
+    let manifest = manifests
+        .into_iter()
+        .find(|manifest| package_name(manifest).as_deref() == Some(name))
+        .ok_or(anyhow!("can't find crate {} in {}", name, dir.display()))?;
+    let crate_dir = manifest.parent().unwrap_or(dir);
+    Ok(crate_dir
+        .strip_prefix(dir)?
+        .components()
+        .map(|x| x.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_the_repo_name() {
+        assert_eq!(
+            repo_name("https://github.com/Byte-OS/polyhal.git"),
+            "polyhal"
+        );
+        assert_eq!(repo_name("https://gitee.com/x/polyhal/"), "polyhal");
+        assert_eq!(repo_name("git@github.com:Byte-OS/polyhal.git"), "polyhal");
+        assert_eq!(repo_name("git@host:polyhal"), "polyhal");
+        assert_eq!(repo_name("file:///tmp/repos/poly.git"), "poly");
+        assert_eq!(
+            repo_name("ssh://git@git.example.com:2222/group/sub/polyhal"),
+            "polyhal"
+        );
+    }
+
+    #[test]
+    fn get_the_sub_path_in_the_cargo_checkout() {
+        let checkout = "/home/x/.cargo/git/checkouts/polyhal-1a2b3c/7f8e9d0";
+        let manifest = |path: &str| PathBuf::from(format!("{checkout}/{path}"));
+        assert_eq!(
+            cargo_checkout_sub_path(&manifest("Cargo.toml")).as_deref(),
+            Some("")
+        );
+        assert_eq!(
+            cargo_checkout_sub_path(&manifest("boot/polyhal-boot/Cargo.toml")).as_deref(),
+            Some("boot/polyhal-boot")
+        );
+        // The `checkouts` in the repository doesn't matter.
+        assert_eq!(
+            cargo_checkout_sub_path(&manifest("checkouts/a/b/c/Cargo.toml")).as_deref(),
+            Some("checkouts/a/b/c")
+        );
+        assert_eq!(
+            cargo_checkout_sub_path(Path::new("/src/polyhal/Cargo.toml")),
+            None
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::Result;
+use color_print::cprintln;
+
+use super::{
+    add_entry, check_origin, clone_target, git::Git, mirror, reuse_checkout,
+    transaction::Transaction, workspace::Workspace, CloneOptions, GitReference, PatchPackage,
+};
+use crate::config::PatchConfig;
+
+/// The remote name of the upstream in the checkout of a fork.
+pub const UPSTREAM_REMOTE: &str = "upstream";
+
+/// The remote name of the fork in the checkout of the upstream.
+pub const FORK_REMOTE: &str = "fork";
+
+/// Where the patched crate is cloned from instead of the locked commit.
+#[derive(Debug, Clone)]
+pub struct Fork {
+    /// The url of the fork, the upstream is used if it's not set.
+    pub url: Option<String>,
+    /// The branch, tag or rev checked out.
+    pub reference: GitReference,
+}
+
+impl Fork {
+    /// Get the name of the reference checked out.
+    fn rev(&self) -> &str {
+        match &self.reference {
+            GitReference::Branch(rev) | GitReference::Tag(rev) | GitReference::Rev(rev) => rev,
+            GitReference::DefaultBranch => "the default branch",
+        }
+    }
+}
+
+/// Get the default branch of the remote.
+fn default_branch(git: &Git, remote: &str) -> Result<String> {
+    if git.dry_run {
+        return Ok(String::from("<default-branch>"));
+    }
+    let head = format!("refs/remotes/{remote}/HEAD");
+    // The head of an added remote isn't known until it's queried.
+    if !git.check(&["rev-parse", "--verify", "-q", &head]) {
+        git.run(&["remote", "set-head", remote, "-a"]).ok();
+    }
+    git.output(&["symbolic-ref", "-q", "--short", &head])
+        .ok()
+        .and_then(|x| x.strip_prefix(&format!("{remote}/")).map(String::from))
+        .ok_or_else(|| anyhow!("can't find the default branch of the fork"))
+}
+
+/// Checkout the branch of the remote, the local branch tracks it.
+fn checkout_tracking(git: &Git, remote: &str, branch: &str) -> Result<()> {
+    let start = format!("{remote}/{branch}");
+    git.run(&["checkout", "-q", "-B", branch, &start])?;
+    git.run(&["branch", "-q", "--set-upstream-to", &start])
+}
+
+/// Checkout the reference of the fork from the remote, the local branch
+/// tracks the branch of the fork if there is one.
+fn checkout_fork(git: &Git, fork: &Fork, remote: &str) -> Result<()> {
+    let is_branch = |rev: &str| {
+        let branch = format!("refs/remotes/{remote}/{rev}");
+        git.check(&["rev-parse", "--verify", "-q", &branch])
+    };
+    match &fork.reference {
+        GitReference::Branch(branch) => checkout_tracking(git, remote, branch),
+        // The rev may be a branch of the fork.
+        GitReference::Rev(rev) if is_branch(rev) => checkout_tracking(git, remote, rev),
+        // The fork isn't fetched in the dry run, so both ways are printed.
+        GitReference::Rev(rev) if git.dry_run => {
+            println!("# {rev} is resolved after fetching the fork, if it's a branch:");
+            checkout_tracking(git, remote, rev)?;
+            println!("# otherwise:");
+            git.run(&["checkout", "-q", "-B", &format!("kbuild/{rev}"), rev])
+        }
+        GitReference::Tag(rev) | GitReference::Rev(rev) => {
+            git.run(&["checkout", "-q", "-B", &format!("kbuild/{rev}"), rev])
+        }
+        GitReference::DefaultBranch => {
+            checkout_tracking(git, remote, &default_branch(git, remote)?)
+        }
+    }
+}
+
+/// Record where the fork leaves the locked commit as the base of `patch export`.
+fn set_base(git: &Git, package: &PatchPackage, url: &str) -> Result<()> {
+    // The fork may not contain the locked commit, or be based on an older one.
+    let base = match git.dry_run {
+        true => Some(package.commit.clone()),
+        false => git.output(&["merge-base", "HEAD", &package.commit]).ok(),
+    };
+    match base {
+        Some(base) => git.set_locked(&base)?,
+        None => cprintln!(
+            "<yellow>{} doesn't share history with the locked commit</yellow>",
+            url
+        ),
+    }
+    Ok(())
+}
+
+/// Checkout the reference of the fork in the existing checkout.
+///
+/// The fork is added as the `fork` remote if it's not the origin. The
+/// checkout is only moved if no other crate is patched to it, the worktree is
+/// clean and every commit is on a remote branch, so nothing is lost.
+fn checkout_existing_fork(
+    ws: &Workspace,
+    git: &Git,
+    checkout: &str,
+    package: &PatchPackage,
+    fork: &Fork,
+    url: &str,
+) -> Result<()> {
+    let origin = git
+        .output(&["remote", "get-url", "origin"])
+        .unwrap_or_default();
+    let remote = match &fork.url {
+        _ if origin == url => "origin",
+        Some(_) => {
+            git.set_remote(FORK_REMOTE, url)?;
+            FORK_REMOTE
+        }
+        // The checkout is cloned from another fork.
+        None if origin != package.git => UPSTREAM_REMOTE,
+        None => "origin",
+    };
+    git.run(&["fetch", "-q", remote])?;
+    // The branch is checked out already, the commits on it are kept.
+    let tracked = git.output(&["rev-parse", "--abbrev-ref", "@{upstream}"]);
+    if tracked.is_ok_and(|x| x == format!("{remote}/{}", fork.rev())) {
+        return set_base(git, package, url);
+    }
+    let patched_table = ws.get_patched_table()?;
+    let patched = patched_table
+        .iter()
+        .find(|x| ws.checkout_root(&x.local) == git.dir);
+    let reason = match patched {
+        Some(patched) => Some(format!("{} is patched to it", patched.name)),
+        None if !git.status()?.is_empty() => Some(String::from("it has uncommitted changes")),
+        None if !git
+            .output(&["rev-list", "HEAD", "--not", "--remotes"])?
+            .is_empty() =>
+        {
+            Some(String::from("it has commits not pushed"))
+        }
+        None => None,
+    };
+    if let Some(reason) = reason {
+        return Err(anyhow!(
+            "can't check out {} of {url} in {checkout}, {reason}",
+            fork.rev()
+        ));
+    }
+    checkout_fork(git, fork, remote)?;
+    set_base(git, package, url)
+}
+
+/// Patch the git package with the fork, the patch entry is still under the
+/// upstream, so the dependencies don't change.
+///
+/// The checkout is `<dir>/<repo>` of the upstream. If the fork isn't the
+/// upstream, the upstream is added as the `upstream` remote and the commit
+/// where the fork leaves the locked commit is the base of `patch export`.
+/// An existing checkout is moved to the reference of the fork.
+pub fn do_patch_fork(
+    ws: &Workspace,
+    tx: &mut Transaction,
+    package: &PatchPackage,
+    fork: &Fork,
+    options: &CloneOptions,
+    config: &PatchConfig,
+) -> Result<()> {
+    let (upstream, checkout) = clone_target(package, options.https, config);
+    let url = fork.url.clone().unwrap_or(upstream.clone());
+    let dir = ws.path(&checkout);
+
+    if reuse_checkout(tx, &dir, &checkout, &url, fork.rev())? {
+        cprintln!("<green>Reusing {} for {}</green>", checkout, package.name);
+    } else if dir.exists() {
+        check_origin(&dir, &checkout, &package.git, &[&url, &upstream])?;
+        cprintln!("<green>Reusing {} for {}</green>", checkout, package.name);
+        let git = Git::new(&dir).dry_run(tx.dry_run);
+        checkout_existing_fork(ws, &git, &checkout, package, fork, &url)?;
+        tx.reuse_dir(&dir, &url, fork.rev());
+    } else {
+        cprintln!("<green>Cloning {} -> {}</green>", url, checkout);
+        tx.clone_dir(&dir, &url, fork.rev())?;
+        let git = match fork.url {
+            Some(_) => {
+                let git = Git::clone(&url, &dir, &["--no-checkout"], tx.dry_run)?;
+                git.run(&["remote", "add", UPSTREAM_REMOTE, &upstream])?;
+                git.run(&["fetch", "-q", UPSTREAM_REMOTE])?;
+                git
+            }
+            None if options.cache => mirror::clone_cached(package, &url, &dir, tx.dry_run, None)?,
+            None => Git::clone(&url, &dir, &["--no-checkout"], tx.dry_run)?,
+        };
+        checkout_fork(&git, fork, "origin")?;
+        set_base(&git, package, &url)?;
+    }
+    add_entry(ws, tx, package, &checkout)
+}
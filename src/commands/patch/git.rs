@@ -0,0 +1,199 @@
+use std::{
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::Result;
+
+use crate::{
+    error::KbuildError,
+    utils::{format_command, spawn},
+};
+
+/// The git repository which the patch command works on.
+///
+/// Every git command of the patch command goes through it, a failed command
+/// is reported as [KbuildError::GitFailed].
+///
+/// In the dry run, the commands changing the repository are printed instead
+/// of being executed.
+#[derive(Debug, Clone)]
+pub struct Git {
+    pub dir: PathBuf,
+    pub dry_run: bool,
+    /// The index used instead of `.git/index`, see `GIT_INDEX_FILE`.
+    index: Option<PathBuf>,
+}
+
+impl Git {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Git {
+            dir: dir.into(),
+            dry_run: false,
+            index: None,
+        }
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn index(mut self, index: impl Into<PathBuf>) -> Self {
+        self.index = Some(index.into());
+        self
+    }
+
+    /// Clone the url into the dir with the extra args.
+    pub fn clone(url: &str, dir: &Path, args: &[&str], dry_run: bool) -> Result<Self> {
+        let mut command = Command::new("git");
+        command.arg("clone").args(args).arg(url).arg(dir);
+        Self::wait(&mut command, "clone", dry_run)?;
+        Ok(Git::new(dir).dry_run(dry_run))
+    }
+
+    /// Clone the url into the dir with the extra args, the progress of git is
+    /// passed to `progress` instead of being printed.
+    pub fn clone_progress(
+        url: &str,
+        dir: &Path,
+        args: &[&str],
+        progress: &mut dyn FnMut(&str),
+    ) -> Result<Self> {
+        let mut command = Command::new("git");
+        command
+            .args(["clone", "--progress"])
+            .args(args)
+            .arg(url)
+            .arg(dir);
+        Self::wait_progress(&mut command, "clone", progress)?;
+        Ok(Git::new(dir))
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new("git");
+        command.args(args).current_dir(&self.dir);
+        if let Some(index) = &self.index {
+            command.env("GIT_INDEX_FILE", index);
+        }
+        command
+    }
+
+    fn wait(command: &mut Command, name: &str, dry_run: bool) -> Result<()> {
+        if dry_run {
+            println!("{}", format_command(command));
+            return Ok(());
+        }
+        let exit_status = spawn(command)?.wait()?;
+        if !exit_status.success() {
+            return Err(KbuildError::GitFailed {
+                command: name.to_string(),
+                status: exit_status.to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Wait for the command, every line of stderr (split by `\r` or `\n`) is
+    /// passed to `progress`. The error of git is reported if the command fails.
+    fn wait_progress(
+        command: &mut Command,
+        name: &str,
+        progress: &mut dyn FnMut(&str),
+    ) -> Result<()> {
+        let mut child = spawn(command.stdout(Stdio::null()).stderr(Stdio::piped()))?;
+        // The first error of git, or the last line.
+        let mut error = None;
+        let mut last = String::new();
+        if let Some(stderr) = child.stderr.take() {
+            let mut line = Vec::new();
+            for byte in BufReader::new(stderr).bytes().map_while(Result::ok) {
+                if byte != b'\r' && byte != b'\n' {
+                    line.push(byte);
+                } else if !line.is_empty() {
+                    last = String::from_utf8_lossy(&line).to_string();
+                    if error.is_none() && (last.starts_with("fatal:") || last.starts_with("error:"))
+                    {
+                        error = Some(last.clone());
+                    }
+                    progress(&last);
+                    line.clear();
+                }
+            }
+        }
+        let exit_status = child.wait()?;
+        if !exit_status.success() {
+            return Err(KbuildError::GitFailed {
+                command: name.to_string(),
+                status: format!("{exit_status}, {}", error.unwrap_or(last)),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Run the git command, the progress is passed to `progress`, see [Git::clone_progress].
+    pub fn run_progress(&self, args: &[&str], progress: &mut dyn FnMut(&str)) -> Result<()> {
+        let name = args.first().unwrap_or(&"");
+        Self::wait_progress(&mut self.command(args), name, progress)
+    }
+
+    /// Run the git command, the output is shown to the user.
+    pub fn run(&self, args: &[&str]) -> Result<()> {
+        let name = args.first().unwrap_or(&"");
+        Self::wait(&mut self.command(args), name, self.dry_run)
+    }
+
+    /// Run the git command and get the stdout, it's executed in the dry run too.
+    pub fn output(&self, args: &[&str]) -> Result<String> {
+        if !self.dir.exists() {
+            return Err(anyhow!("{} doesn't exist", self.dir.display()));
+        }
+        let outputs = spawn(self.command(args).stdout(Stdio::piped()))?.wait_with_output()?;
+        if !outputs.status.success() {
+            return Err(KbuildError::GitFailed {
+                command: args.join(" "),
+                status: outputs.status.to_string(),
+            }
+            .into());
+        }
+        Ok(String::from_utf8(outputs.stdout)?.trim().to_string())
+    }
+
+    /// Check if the command succeeds, the output is dropped.
+    pub fn check(&self, args: &[&str]) -> bool {
+        let mut command = self.command(args);
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+        spawn(&mut command)
+            .and_then(|mut child| Ok(child.wait()?))
+            .is_ok_and(|status| status.success())
+    }
+
+    pub fn head(&self) -> Result<String> {
+        self.output(&["rev-parse", "HEAD"])
+    }
+
+    /// Get the uncommitted changes in `git status -s` format.
+    pub fn status(&self) -> Result<String> {
+        self.output(&["status", "-s"])
+    }
+
+    /// Get the commit recorded as the base of the patched crate.
+    pub fn locked(&self) -> Result<String> {
+        self.output(&["config", "--get", "kbuild.locked"])
+    }
+
+    pub fn set_locked(&self, commit: &str) -> Result<()> {
+        self.run(&["config", "kbuild.locked", commit])
+    }
+
+    /// Add the remote, or change its url if it exists.
+    pub fn set_remote(&self, name: &str, url: &str) -> Result<()> {
+        match self.check(&["remote", "get-url", name]) {
+            true => self.run(&["remote", "set-url", name, url]),
+            false => self.run(&["remote", "add", name, url]),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use color_print::cprintln;
+
+use super::{cargo_home, checkout::repo_name, git::Git, GitReference, PatchPackage};
+
+/// The progress of git, see [Git::run_progress]. The output of git is shown
+/// to the user if it's `None`.
+pub type Progress<'a> = Option<&'a mut dyn FnMut(&str)>;
+
+/// Get the directory of the mirrors, `$KBUILD_CACHE_DIR`,
+/// `$XDG_CACHE_HOME/kbuild/git` or `~/.cache/kbuild/git`.
+pub fn cache_dir() -> Result<PathBuf> {
+    if let Some(dir) = env::var_os("KBUILD_CACHE_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    let cache = match env::var_os("XDG_CACHE_HOME") {
+        Some(cache) => PathBuf::from(cache),
+        None => env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(".cache"))
+            .ok_or_else(|| anyhow!("can't find the home directory"))?,
+    };
+    Ok(cache.join("kbuild/git"))
+}
+
+/// Get the mirror of the git url, e.g. `<cache>/github.com/x/y.git`.
+///
+/// The https and ssh urls of a repository share one mirror.
+pub fn mirror_dir(git: &str) -> Result<PathBuf> {
+    let path = git.split_once("://").map_or(git, |(_, path)| path);
+    let path = path.split_once('@').map_or(path, |(_, path)| path);
+    let path = path.replacen(':', "/", 1);
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+    Ok(cache_dir()?.join(format!("{}.git", path.trim_start_matches('/'))))
+}
+
+fn has_commit(git: &Git, commit: &str) -> bool {
+    git.check(&["cat-file", "-e", &format!("{commit}^{{commit}}")])
+}
+
+/// Find the repository in `~/.cargo/git/db` holding the commit.
+fn cargo_db(git: &str, commit: &str) -> Option<PathBuf> {
+    let prefix = format!("{}-", repo_name(git));
+    fs::read_dir(cargo_home().ok()?.join("git/db"))
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .find(|path| has_commit(&Git::new(path), commit))
+}
+
+fn run(git: &Git, args: &[&str], progress: &mut Progress) -> Result<()> {
+    match progress {
+        Some(progress) => {
+            let args = [&args[..1], &["--progress"], &args[1..]].concat();
+            git.run_progress(&args, *progress)
+        }
+        None => git.run(args),
+    }
+}
+
+/// Fetch the upstream of `git` from `url` into the mirror, the mirror is
+/// created if it doesn't exist.
+///
+/// Nothing is fetched if the mirror holds the `commit` already. If the upstream
+/// can't be fetched, the commit is taken from `~/.cargo/git/db`, so the locked
+/// commit is available offline.
+///
+/// In the dry run, the commands to update the mirror are printed.
+pub fn update_mirror(
+    dir: &Path,
+    git: &str,
+    url: &str,
+    commit: Option<&str>,
+    dry_run: bool,
+    mut progress: Progress,
+) -> Result<Git> {
+    let mirror = Git::new(dir).dry_run(dry_run);
+    let exists = dir.exists();
+    if exists && commit.is_some_and(|commit| has_commit(&mirror, commit)) {
+        return Ok(mirror);
+    }
+
+    let fetched = match exists {
+        true => run(&mirror, &["fetch", "origin"], &mut progress),
+        false => {
+            let parent = dir.parent().unwrap_or(dir);
+            if !dry_run {
+                fs::create_dir_all(parent)?;
+            }
+            let clone = ["clone", "--mirror", url, &dir.to_string_lossy()];
+            run(&Git::new(parent).dry_run(dry_run), &clone, &mut progress)
+        }
+    };
+    let Some(commit) = commit.filter(|_| !dry_run) else {
+        return fetched.map(|_| mirror);
+    };
+    if !has_commit(&mirror, commit) {
+        let Some(db) = cargo_db(git, commit) else {
+            fetched?;
+            return Err(anyhow!("can't find the locked commit {commit} in {git}"));
+        };
+        let db = db.to_string_lossy();
+        if !dir.exists() {
+            // The upstream isn't available, the mirror is filled by cargo, which
+            // keeps the branches in `refs/remotes/origin`.
+            fs::create_dir_all(dir)?;
+            mirror.run(&["init", "-q", "--bare"])?;
+            mirror.run(&["remote", "add", "--mirror=fetch", "origin", url])?;
+            mirror.run(&["fetch", "-q", &db, "+refs/remotes/origin/*:refs/heads/*"])?;
+        }
+        // The ref keeps the commit from being pruned.
+        let refspec = format!("{commit}:refs/kbuild/{commit}");
+        mirror.run(&["fetch", "-q", &db, &refspec])?;
+    }
+    Ok(mirror)
+}
+
+/// Clone the package from the mirror, the mirror is updated first. The
+/// origin of the checkout is the upstream url.
+pub fn clone_cached(
+    package: &PatchPackage,
+    url: &str,
+    dir: &Path,
+    dry_run: bool,
+    progress: Progress,
+) -> Result<Git> {
+    let mirror = mirror_dir(&package.git)?;
+    // A new branch isn't in the mirror even if the commit is, it's fetched
+    // when the upstream is available.
+    if let GitReference::Branch(branch) = &package.rev {
+        let mirror_git = Git::new(&mirror).dry_run(dry_run);
+        let head = format!("refs/heads/{branch}");
+        if mirror.exists() && !mirror_git.check(&["rev-parse", "--verify", "-q", &head]) {
+            mirror_git.run(&["fetch", "-q", "origin"]).ok();
+        }
+    }
+    let commit = Some(package.commit.as_str());
+    update_mirror(&mirror, &package.git, url, commit, dry_run, progress)?;
+
+    let source = mirror.to_string_lossy();
+    let git = Git::clone(&source, dir, &["-q", "--no-checkout"], dry_run)?;
+    // The commit from cargo isn't on a branch of the mirror.
+    if !dry_run && !has_commit(&git, &package.commit) {
+        git.run(&["fetch", "-q", &source, &package.commit])?;
+    }
+    git.run(&["remote", "set-url", "origin", url])?;
+    Ok(git)
+}
+
+/// Fetch the upstream urls into the mirrors.
+///
+/// A failed mirror is reported and doesn't stop others.
+pub fn fetch(mirrors: &[(PathBuf, String)]) -> Result<()> {
+    let mut failed = 0;
+    for (dir, url) in mirrors {
+        cprintln!("<green>Fetching {}</green>", url);
+        if let Err(err) = update_mirror(dir, url, url, None, false, None) {
+            failed += 1;
+            cprintln!("    <red>{}</red>", err);
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{failed} mirrors are not fetched"));
+    }
+    Ok(())
+}
+
+/// Get every mirror in the cache and the url it's fetched from.
+pub fn cached_mirrors() -> Result<Vec<(PathBuf, String)>> {
+    let mut dirs = vec![cache_dir()?];
+    let mut mirrors = Vec::new();
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+            if !path.is_dir() {
+                continue;
+            }
+            if path.extension().is_none_or(|x| x != "git") {
+                dirs.push(path);
+                continue;
+            }
+            if let Ok(url) = Git::new(&path).output(&["remote", "get-url", "origin"]) {
+                mirrors.push((path, url));
+            }
+        }
+    }
+    mirrors.sort();
+    Ok(mirrors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn share_the_mirror_between_urls() {
+        let mirror = |git: &str| {
+            let dir = mirror_dir(git).unwrap();
+            dir.strip_prefix(cache_dir().unwrap())
+                .unwrap()
+                .to_path_buf()
+        };
+        let expected = Path::new("github.com/Byte-OS/polyhal.git");
+        for git in [
+            "https://github.com/Byte-OS/polyhal.git",
+            "https://github.com/Byte-OS/polyhal",
+            "https://github.com/Byte-OS/polyhal/",
+            "git@github.com:Byte-OS/polyhal.git",
+            "ssh://git@github.com/Byte-OS/polyhal",
+        ] {
+            assert_eq!(mirror(git), expected, "{git}");
+        }
+        assert_eq!(mirror("file:///tmp/poly.git"), Path::new("tmp/poly.git"));
+    }
+}
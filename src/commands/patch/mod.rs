@@ -0,0 +1,1135 @@
+mod checkout;
+mod fork;
+mod git;
+mod mirror;
+mod patch_all;
+mod push;
+mod series;
+mod status;
+mod sync;
+mod transaction;
+mod update;
+mod workspace;
+
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{Ok, Result};
+use checkout::{cargo_checkout_sub_path, find_crate_path, repo_name};
+use color_print::cprintln;
+use fork::Fork;
+use git::Git;
+use push::PushOptions;
+use transaction::Transaction;
+use workspace::{is_registry_source, CargoPackage, PatchedPackage, Workspace};
+
+use crate::{
+    commands::build::cargo_command,
+    config::{read_bin_config, read_config, PatchConfig},
+    error::KbuildError,
+    utils::{format_command, get_option, get_positional, has_flag, spawn, wildcard_match},
+    CommandAndHandler,
+};
+
+/// The options to clone the repositories of the patched crates.
+#[derive(Debug, Clone)]
+pub struct CloneOptions {
+    pub https: bool,
+    /// The number of clones running at once in `patch_all`.
+    pub jobs: usize,
+    /// Clone the tips of the branches only, the locked commit is fetched if needed.
+    /// It's only used without `cache`.
+    pub shallow: bool,
+    /// Clone from the mirrors in the cache, see [mirror::update_mirror].
+    pub cache: bool,
+}
+
+/// The git reference declared in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitReference {
+    Branch(String),
+    Tag(String),
+    Rev(String),
+    DefaultBranch,
+}
+
+/// The git package parsed from the source in Cargo.lock.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchPackage {
+    name: String,
+    git: String,
+    /// The `branch`, `tag` or `rev` declared in the manifest.
+    rev: GitReference,
+    /// The commit locked in Cargo.lock.
+    commit: String,
+    /// The path of the crate in the repository, read from the cargo checkout.
+    sub_path: Option<String>,
+}
+
+impl PatchPackage {
+    /// Parse the source like `git+https://github.com/x/y?branch=main#<commit>`.
+    ///
+    /// The reference declared in the manifests is used, the query of the
+    /// source is the fallback for the crate not declared by the workspace.
+    fn from_package(ws: &Workspace, package: &CargoPackage) -> Result<Self> {
+        let source = package.source.as_deref().unwrap_or_default();
+        let (url, commit) = source
+            .strip_prefix("git+")
+            .and_then(|x| x.split_once('#'))
+            .ok_or(anyhow!("This is not a valid patch source"))?;
+        let (git, query) = url.split_once('?').unwrap_or((url, ""));
+        let rev = match ws.declared_reference(&package.name, git)? {
+            Some(rev) => rev,
+            None => match query.split_once('=') {
+                Some(("branch", branch)) => GitReference::Branch(percent_decode(branch)),
+                Some(("tag", tag)) => GitReference::Tag(percent_decode(tag)),
+                Some(("rev", rev)) => GitReference::Rev(percent_decode(rev)),
+                _ => GitReference::DefaultBranch,
+            },
+        };
+        Ok(PatchPackage {
+            name: package.name.clone(),
+            git: git.to_string(),
+            rev,
+            commit: commit.to_string(),
+            sub_path: cargo_checkout_sub_path(&package.manifest_path),
+        })
+    }
+}
+
+/// Decode the `%XX` escapes in the url, e.g. `dev%2Ffoo` -> `dev/foo`.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+pub fn check_patch_table(ws: &Workspace, registry: bool) -> Result<()> {
+    println!("Patch table avaliable in the below");
+    for x in ws.get_patch_table(registry)? {
+        cprintln!(
+            "    <green><bold>{:20}</bold></green> {:10} {}",
+            x.name,
+            x.version,
+            x.source.as_ref().unwrap()
+        );
+        let members: Vec<_> = x
+            .direct
+            .iter()
+            .map(|member| (member, "direct"))
+            .chain(x.transitive.iter().map(|member| (member, "transitive")))
+            .collect();
+        for (i, (member, kind)) in members.iter().enumerate() {
+            let branch = if i + 1 == members.len() {
+                "└─"
+            } else {
+                "├─"
+            };
+            println!("        {} {} ({})", branch, member, kind);
+        }
+    }
+    println!();
+    println!("Patched table in the below");
+    ws.get_patched_table()?.iter().for_each(|x| {
+        cprintln!(
+            "    <green><bold>{:20}</bold></green> {:22} -> {}",
+            x.name,
+            x.local,
+            x.git
+        );
+    });
+    Ok(())
+}
+
+/// Find the package to patch, `name@version` can be used if the name is ambiguous.
+fn find_patch<'a>(patch_table: &'a [CargoPackage], spec: &str) -> Result<&'a CargoPackage> {
+    let (name, version) = spec.split_once('@').unwrap_or((spec, ""));
+    let matched: Vec<_> = patch_table
+        .iter()
+        .filter(|x| x.name == name && (version.is_empty() || x.version == version))
+        .collect();
+    match matched.len() {
+        0 => Err(anyhow!("Can't find matched patch name")),
+        1 => Ok(matched[0]),
+        _ => {
+            let candidates: Vec<_> = matched
+                .iter()
+                .map(|x| {
+                    format!(
+                        "    {}@{} {}",
+                        x.name,
+                        x.version,
+                        x.source.as_ref().unwrap()
+                    )
+                })
+                .collect();
+            Err(anyhow!(
+                "{spec} is ambiguous, use name@version to choose one of\n{}",
+                candidates.join("\n")
+            ))
+        }
+    }
+}
+
+/// Convert the https url to ssh url through the rules in [PatchConfig::ssh].
+///
+/// The url which is not http(s) (e.g. `file://`, `ssh://`) is returned directly.
+fn git_https_to_ssh(git: &str, config: &PatchConfig) -> String {
+    let Some(url) = git
+        .strip_prefix("https://")
+        .or_else(|| git.strip_prefix("http://"))
+    else {
+        return git.to_string();
+    };
+    let (host, path) = url.split_once('/').unwrap_or((url, ""));
+    match config.ssh.get(host) {
+        Some(prefix) => format!("{prefix}{path}"),
+        None => format!("git@{host}:{path}"),
+    }
+}
+
+/// Get the config file from `--config`, or `default.toml` in the current
+/// directory or the workspace root if it exists.
+fn config_path(args: &[String], ws: &Workspace) -> Option<PathBuf> {
+    match get_option(args, "--config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => [PathBuf::from("default.toml"), ws.path("default.toml")]
+            .into_iter()
+            .find(|path| path.exists()),
+    }
+}
+
+/// Load the patch config from the config file, see [config_path].
+///
+/// `--dir` overrides the patch directory in the config.
+fn load_patch_config(args: &[String], ws: &Workspace) -> Result<PatchConfig> {
+    let mut config = match config_path(args, ws) {
+        Some(path) => read_config(&path.to_string_lossy())?.patch,
+        None => PatchConfig::default(),
+    };
+    if let Some(dir) = get_option(args, "--dir") {
+        config.dir = Some(dir);
+    }
+    Ok(config)
+}
+
+/// Checkout a local branch at the locked commit.
+///
+/// The branch tracks the declared branch, or the default branch of the remote
+/// if the dependency is declared with a tag or rev.
+fn checkout_locked(git: &Git, package: &PatchPackage) -> Result<()> {
+    let default_branch = match git.dry_run {
+        // The repository isn't cloned in the dry run.
+        true => Some(String::from("<default-branch>")),
+        false => {
+            git.output(&["cat-file", "-e", &format!("{}^{{commit}}", package.commit)])
+                .map_err(|_| {
+                    anyhow!(
+                        "can't find the locked commit {} in {}",
+                        package.commit,
+                        package.git
+                    )
+                })?;
+            git.output(&["symbolic-ref", "-q", "--short", "refs/remotes/origin/HEAD"])
+                .ok()
+                .and_then(|x| x.strip_prefix("origin/").map(String::from))
+        }
+    };
+    let (local, upstream) = match package.rev {
+        GitReference::Branch(ref branch) => (branch.clone(), Some(branch.clone())),
+        GitReference::Tag(ref tag) => (format!("kbuild/{tag}"), default_branch),
+        GitReference::Rev(ref rev) => (format!("kbuild/{rev}"), default_branch),
+        GitReference::DefaultBranch => (
+            default_branch
+                .clone()
+                .unwrap_or(String::from("kbuild/locked")),
+            default_branch,
+        ),
+    };
+    git.run(&["checkout", "-q", "-B", &local, &package.commit])?;
+    // The base of `patch export`.
+    git.set_locked(&package.commit)?;
+    // The branch may be missing in a mirror filled by cargo.
+    let upstream = upstream.map(|x| format!("origin/{x}")).filter(|x| {
+        git.dry_run || git.check(&["rev-parse", "--verify", "-q", &format!("refs/remotes/{x}")])
+    });
+    if let Some(upstream) = upstream {
+        git.run(&["branch", "-q", "--set-upstream-to", &upstream])?;
+    }
+    Ok(())
+}
+
+/// Check if the name matches one of the comma separated globs.
+fn match_globs(globs: &str, name: &str) -> bool {
+    globs
+        .split(',')
+        .any(|glob| wildcard_match(glob.trim(), name))
+}
+
+/// Get the url to clone and the checkout of the package, relative to the workspace root.
+fn clone_target(package: &PatchPackage, https: bool, config: &PatchConfig) -> (String, String) {
+    let url = match https {
+        true => package.git.clone(),
+        false => git_https_to_ssh(&package.git, config),
+    };
+    let checkout = format!("{}/{}", config.get_dir(), repo_name(&package.git));
+    (url, checkout)
+}
+
+/// Check if the existing checkout is cloned from the git repository or one
+/// of the urls, or from a fork of it with the `upstream` remote.
+fn check_origin(dir: &Path, checkout: &str, git: &str, urls: &[&str]) -> Result<()> {
+    let repo = Git::new(dir);
+    let known = |remote: &str| {
+        let url = repo
+            .output(&["remote", "get-url", remote])
+            .unwrap_or_default();
+        url == git || urls.contains(&url.as_str())
+    };
+    if !known("origin") && !known(fork::UPSTREAM_REMOTE) {
+        return Err(anyhow!(
+            "{checkout} is not a checkout of {git}, use --dir to choose another directory"
+        ));
+    }
+    Ok(())
+}
+
+/// Check if the checkout is used in the transaction, it can only be reused
+/// if it's cloned from the same url and checked out at the same revision.
+fn reuse_checkout(
+    tx: &Transaction,
+    dir: &Path,
+    checkout: &str,
+    url: &str,
+    rev: &str,
+) -> Result<bool> {
+    match tx.checkout(dir) {
+        Some((used, _)) if used != url => Err(anyhow!(
+            "{checkout} is cloned from {used} already, use --dir to choose another directory"
+        )),
+        Some((_, used)) if used != rev => Err(anyhow!(
+            "{checkout} is checked out at {used} for another crate, but {rev} is required"
+        )),
+        used => Ok(used.is_some()),
+    }
+}
+
+/// Move the existing checkout to the locked commit of the package.
+///
+/// It's only moved if no other crate is patched to it and nothing would be
+/// lost, i.e. the worktree is clean and every commit is on a remote branch.
+fn checkout_existing(
+    ws: &Workspace,
+    tx: &Transaction,
+    dir: &Path,
+    checkout: &str,
+    package: &PatchPackage,
+) -> Result<()> {
+    let git = Git::new(dir).dry_run(tx.dry_run);
+    let head = git.head().unwrap_or_default();
+    if head == package.commit {
+        return Ok(());
+    }
+    let conflict = |reason: String| {
+        anyhow!(
+            "{checkout} is at {head} instead of the locked commit {} of {}, {reason}",
+            package.commit,
+            package.name
+        )
+    };
+    let patched_table = ws.get_patched_table()?;
+    if let Some(patched) = patched_table
+        .iter()
+        .find(|x| ws.checkout_root(&x.local) == dir)
+    {
+        return Err(conflict(format!("{} is patched to it", patched.name)));
+    }
+    if !git.status()?.is_empty() {
+        return Err(conflict(String::from("it has uncommitted changes")));
+    }
+    if !git
+        .output(&["rev-list", "HEAD", "--not", "--remotes"])?
+        .is_empty()
+    {
+        return Err(conflict(String::from("it has commits not pushed")));
+    }
+    cprintln!(
+        "<yellow>Checking out the locked commit {} in {}</yellow>",
+        package.commit,
+        checkout
+    );
+    checkout_locked(&git, package)
+}
+
+/// Add the patch entry pointing to the crate in the checkout.
+fn add_entry(
+    ws: &Workspace,
+    tx: &Transaction,
+    package: &PatchPackage,
+    checkout: &str,
+) -> Result<()> {
+    // The sub-path in the cargo checkout is used in the dry run, since nothing is cloned.
+    let sub_path = match tx.dry_run {
+        true => package.sub_path.clone().unwrap_or_default(),
+        false => find_crate_path(&ws.path(checkout), &package.name)?,
+    };
+    let local = match sub_path.as_str() {
+        "" => checkout.to_string(),
+        sub_path => format!("{checkout}/{sub_path}"),
+    };
+    cprintln!("<green>Patching {} -> {}</green>", package.name, local);
+    ws.add_patch_entry(&package.git, &package.name, &local)
+}
+
+/// Patch the git package, the repository is cloned into `<dir>/<repo>`.
+///
+/// Crates from the same repository share one checkout, the patch entry points
+/// to the sub-path of the crate in the checkout.
+pub fn do_patch(
+    ws: &Workspace,
+    tx: &mut Transaction,
+    package: &PatchPackage,
+    options: &CloneOptions,
+    config: &PatchConfig,
+) -> Result<()> {
+    let (url, checkout) = clone_target(package, options.https, config);
+    let dir = ws.path(&checkout);
+
+    if reuse_checkout(tx, &dir, &checkout, &url, &package.commit)? {
+        cprintln!("<green>Reusing {} for {}</green>", checkout, package.name);
+    } else if dir.exists() {
+        check_origin(&dir, &checkout, &package.git, &[&url])?;
+        cprintln!("<green>Reusing {} for {}</green>", checkout, package.name);
+        checkout_existing(ws, tx, &dir, &checkout, package)?;
+        tx.reuse_dir(&dir, &url, &package.commit);
+    } else {
+        cprintln!(
+            "<green>Cloning {}:{} -> {}</green>",
+            url,
+            package.commit,
+            checkout
+        );
+        tx.clone_dir(&dir, &url, &package.commit)?;
+        let git = match options.cache {
+            true => mirror::clone_cached(package, &url, &dir, tx.dry_run, None)?,
+            false => Git::clone(&url, &dir, &[], tx.dry_run)?,
+        };
+        checkout_locked(&git, package)?;
+    }
+    add_entry(ws, tx, package, &checkout)
+}
+
+/// Get the cargo home, `CARGO_HOME` or `~/.cargo`.
+fn cargo_home() -> Result<PathBuf> {
+    if let Some(home) = env::var_os("CARGO_HOME") {
+        return Ok(PathBuf::from(home));
+    }
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".cargo"))
+        .ok_or(anyhow!("can't find the cargo home"))
+}
+
+/// The source of crates.io in Cargo.lock.
+const CRATES_IO: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+/// Get the prefixes of the cache directories (`<host>-<hash>`) of the registry.
+fn registry_cache_prefixes(source: &str) -> Vec<String> {
+    // crates.io is cached as `index.crates.io` through the sparse protocol.
+    if source == CRATES_IO {
+        return vec![
+            String::from("index.crates.io-"),
+            String::from("github.com-"),
+        ];
+    }
+    let url = source.split_once('+').map_or(source, |(_, url)| url);
+    let host = url.split_once("://").map_or(url, |(_, path)| path);
+    let host = host.split('/').next().unwrap_or_default();
+    vec![format!("{host}-")]
+}
+
+/// Find the `.crate` file of the package in the cache of its registry.
+fn find_registry_crate(source: &str, name: &str, version: &str) -> Result<Option<PathBuf>> {
+    let cache = cargo_home()?.join("registry/cache");
+    let Result::Ok(indexes) = fs::read_dir(cache) else {
+        return Ok(None);
+    };
+    let prefixes = registry_cache_prefixes(source);
+    Ok(indexes
+        .flatten()
+        .filter(|index| {
+            let index = index.file_name().to_string_lossy().to_string();
+            prefixes.iter().any(|prefix| index.starts_with(prefix))
+        })
+        .map(|index| index.path().join(format!("{name}-{version}.crate")))
+        .find(|path| path.exists()))
+}
+
+/// Vendor the package through `cargo vendor` and move it to the dir.
+///
+/// All the dependencies are vendored into `target/kbuild/vendor`, which is
+/// deleted afterwards.
+fn vendor_crate(ws: &Workspace, name: &str, version: &str, dir: &Path) -> Result<()> {
+    let vendor = ws.path("target/kbuild/vendor");
+    let mut command = Command::new("cargo");
+    command
+        .args(["vendor", "-q", "--versioned-dirs"])
+        .arg(&vendor)
+        .current_dir(&ws.root)
+        .stdout(Stdio::null());
+    let vendored = vendor.join(format!("{name}-{version}"));
+    if ws.is_dry_run() {
+        println!("{}", format_command(&command));
+        println!("mv {} {}", vendored.display(), dir.display());
+        return Ok(());
+    }
+    let exit_status = spawn(&mut command)?.wait()?;
+    if !exit_status.success() {
+        return Err(anyhow!("can't vendor {name}-{version}, {exit_status}"));
+    }
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let moved = fs::rename(&vendored, dir);
+    fs::remove_dir_all(&vendor)?;
+    moved.map_err(|err| anyhow!("can't find {name}-{version} in the vendored crates, {err}"))?;
+    // The checksums of the vendored crate are useless for a path dependency.
+    let checksum = dir.join(".cargo-checksum.json");
+    if checksum.exists() {
+        fs::remove_file(checksum)?;
+    }
+    Ok(())
+}
+
+/// Patch the registry package, the crate is unpacked from the cache of the
+/// registry, or vendored by `cargo vendor` if it isn't cached.
+///
+/// If `git_init` is true, a git repository is created, so the changes can be tracked.
+pub fn do_patch_registry(
+    ws: &Workspace,
+    tx: &mut Transaction,
+    name: &str,
+    version: &str,
+    source: &str,
+    git_init: bool,
+    config: &PatchConfig,
+) -> Result<()> {
+    let local = format!("{}/{}", config.get_dir(), name);
+    cprintln!("<green>Patching {}@{} -> {}</green>", name, version, local);
+
+    let dir = ws.path(&local);
+    tx.create_dir(&dir)?;
+    match find_registry_crate(source, name, version)? {
+        Some(crate_file) => {
+            let mut tar = Command::new("tar");
+            tar.arg("-xzf")
+                .arg(&crate_file)
+                .arg("--strip-components=1")
+                .arg("-C")
+                .arg(&dir);
+            if tx.dry_run {
+                println!("{}", format_command(&tar));
+            } else {
+                fs::create_dir_all(&dir)?;
+                let exit_status = spawn(&mut tar)?.wait()?;
+                if !exit_status.success() {
+                    return Err(anyhow!(
+                        "can't unpack {}, {exit_status}",
+                        crate_file.display()
+                    ));
+                }
+            }
+        }
+        None => {
+            cprintln!(
+                "<yellow>{}-{}.crate isn't in the cargo registry cache, vendoring it</yellow>",
+                name,
+                version
+            );
+            vendor_crate(ws, name, version, &dir)?;
+        }
+    }
+
+    if git_init {
+        let git = Git::new(&dir).dry_run(tx.dry_run);
+        git.run(&["init", "-q"])?;
+        git.run(&["add", "-A"])?;
+        git.run(&["commit", "-q", "-m", &format!("{name} {version}")])?;
+        if !tx.dry_run {
+            git.set_locked(&git.head()?)?;
+        }
+    }
+
+    // crates.io is patched through `[patch.crates-io]`, others through the index url.
+    let key = match source {
+        CRATES_IO => "crates-io",
+        _ => source.strip_prefix("registry+").unwrap_or(source),
+    };
+    ws.add_patch_entry(key, name, &local)
+}
+
+/// Check if the checkout of the patched crate has no changes.
+fn check_clean(ws: &Workspace, name: &str, local: &str) -> Result<()> {
+    let root = ws.checkout_root(local);
+    if !root.join(".git").exists() {
+        return Err(KbuildError::DirtyPatch {
+            name: name.to_string(),
+            files: format!("{local} is not a git repository, use --force to remove it"),
+        }
+        .into());
+    }
+    let files = Git::new(root).status()?;
+    if !files.is_empty() {
+        return Err(KbuildError::DirtyPatch {
+            name: name.to_string(),
+            files,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Make sure Cargo.lock is up to date before reading it.
+///
+/// `cargo generate-lockfile` is run if Cargo.lock is missing. If the root
+/// manifest or a member manifest is newer than it, `cargo update --workspace`
+/// is run, so the locked versions of other crates are kept. In the dry run,
+/// it fails instead.
+fn ensure_lockfile(ws: &Workspace) -> Result<()> {
+    let lockfile = ws.path("Cargo.lock");
+    let mut command = Command::new("cargo");
+    command.current_dir(&ws.root);
+    match fs::metadata(&lockfile).and_then(|x| x.modified()) {
+        Err(_) => command.arg("generate-lockfile"),
+        Result::Ok(locked) => {
+            // The patched crates aren't members, their manifests don't matter.
+            let stale = ws.member_manifests()?.iter().any(|manifest| {
+                fs::metadata(manifest)
+                    .and_then(|x| x.modified())
+                    .is_ok_and(|modified| modified > locked)
+            });
+            if !stale {
+                return Ok(());
+            }
+            command.args(["update", "--workspace"])
+        }
+    };
+    // Nothing is written in the dry run, `cargo metadata` needs Cargo.lock.
+    if ws.is_dry_run() {
+        return Err(anyhow!(
+            "Cargo.lock is missing or stale, run `{}` before --dry-run",
+            format_command(&command)
+        ));
+    }
+    let exit_status = spawn(&mut command)?.wait()?;
+    if !exit_status.success() {
+        return Err(anyhow!("can't update Cargo.lock, {exit_status}"));
+    }
+    Ok(())
+}
+
+/// Check the workspace with the target, rustflags and envs of the bin.
+fn check_bin(ws: &Workspace, config: Option<PathBuf>, bin: &str) -> Result<()> {
+    let config = config.ok_or(anyhow!("can't find the config file, use --config <file>"))?;
+    let binary_config = read_bin_config(&config.to_string_lossy(), bin)?;
+    let mut command = cargo_command(&binary_config, "check", &ws.root);
+    command.current_dir(&ws.root);
+    if ws.is_dry_run() {
+        println!("{}", format_command(&command));
+        return Ok(());
+    }
+    let exit_status = spawn(&mut command)?.wait()?;
+    if !exit_status.success() {
+        return Err(KbuildError::BuildFailed {
+            bin: bin.to_string(),
+            status: exit_status.to_string(),
+            diagnostics: false,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Find the patched crate in Cargo.toml by name.
+fn find_patched(ws: &Workspace, name: &str) -> Result<PatchedPackage> {
+    ws.get_patched_table()?
+        .into_iter()
+        .find(|x| x.name == name)
+        .ok_or(anyhow!("can't find any matched package named {}", name))
+}
+
+/// Remove the patches from Cargo.toml and delete their checkouts.
+///
+/// Every checkout is checked before anything is changed.
+///
+/// A checkout shared by several crates is only deleted when none of the
+/// remaining patches lives in it. Only the checkouts created by kbuild in the
+/// patch directory are deleted, the other paths are left alone.
+fn remove_patches(
+    ws: &Workspace,
+    tx: &mut Transaction,
+    patches: &[PatchedPackage],
+    force: bool,
+    config: &PatchConfig,
+) -> Result<()> {
+    let is_removed = |x: &PatchedPackage| {
+        patches
+            .iter()
+            .any(|patch| patch.git == x.git && patch.name == x.name)
+    };
+    let remaining: Vec<PathBuf> = ws
+        .get_patched_table()?
+        .iter()
+        .filter(|x| !is_removed(x))
+        .map(|x| ws.checkout_root(&x.local))
+        .collect();
+    let mut roots = Vec::new();
+    for patch in patches {
+        let root = ws.checkout_root(&patch.local);
+        if remaining.contains(&root) || roots.contains(&root) {
+            continue;
+        }
+        // kbuild records the locked commit in the checkouts it clones.
+        let created = ws.is_below(config.get_dir(), &root)
+            && (!root.join(".git").exists() || Git::new(&root).locked().is_ok());
+        if !created {
+            cprintln!(
+                "<yellow>{} isn't created by kbuild, it's kept</yellow>",
+                patch.local
+            );
+            continue;
+        }
+        if !force {
+            check_clean(ws, &patch.name, &patch.local)?;
+        }
+        roots.push(root);
+    }
+
+    ws.remove_patch_entries(patches)?;
+    for root in roots {
+        if root.exists() {
+            tx.remove_dir(&root)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the patch commands.
+fn print_help() {
+    cprintln!("Patch commands availible below");
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "list",
+        "list patch available"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "status",
+        "show the state of the patched crates"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "add",
+        "Download and patch into Cargo.toml"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "remove",
+        "remove patch from Cargo.toml and delete folder"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "update",
+        "rebase the patched crates onto the upstream"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "export",
+        "save the changes of the patched crate into patches/<name>"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "apply",
+        "apply the changes in patches/<name> to the patched crates"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "push",
+        "push the patched crate to the fork and use it in Cargo.toml"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "fetch",
+        "fetch the upstream of the git dependencies into the mirror cache"
+    );
+    cprintln!(
+        "    <green>{:20}</green> {}",
+        "sync",
+        "patch the crates listed in the [patch] section of the config"
+    );
+}
+
+/// The options of the patch commands which take a value.
+const VALUE_OPTIONS: &[&str] = &[
+    "--config",
+    "--dir",
+    "--remote",
+    "--branch",
+    "--message",
+    "--rev",
+    "--check",
+    "--jobs",
+    "--only",
+    "--exclude",
+    "--from",
+];
+
+/// The command handler of the help command.
+pub fn handler(args: Vec<String>) -> Result<()> {
+    let positional = get_positional(&args, VALUE_OPTIONS);
+    // The help is printed outside of a cargo project too.
+    if positional.len() == 2 {
+        print_help();
+        return Ok(());
+    }
+    let mut ws = Workspace::locate()?;
+    let config = load_patch_config(&args, &ws)?;
+    let dry_run = has_flag(&args, "--dry-run");
+    let check = get_option(&args, "--check");
+    let options = CloneOptions {
+        https: has_flag(&args, "--https"),
+        jobs: match get_option(&args, "--jobs") {
+            Some(jobs) => jobs
+                .parse()
+                .map_err(|err| anyhow!("invalid --jobs {jobs}, {err}"))?,
+            None => 4,
+        },
+        // The mirrors hold the whole history, so a shallow clone skips them.
+        shallow: has_flag(&args, "--shallow")
+            || (has_flag(&args, "--no-cache") && !has_flag(&args, "--full")),
+        cache: !has_flag(&args, "--no-cache") && !has_flag(&args, "--shallow"),
+    };
+    let config_file = config_path(&args, &ws);
+    let force = has_flag(&args, "--force");
+    let registry = has_flag(&args, "--registry");
+    let git_init = has_flag(&args, "--git-init");
+    let all = has_flag(&args, "--all");
+    let yes = has_flag(&args, "--yes");
+    let rev = get_option(&args, "--rev");
+    let from = get_option(&args, "--from");
+    let only = get_option(&args, "--only");
+    let exclude = get_option(&args, "--exclude");
+    let push_options = PushOptions {
+        remote: get_option(&args, "--remote"),
+        branch: get_option(&args, "--branch"),
+        message: get_option(&args, "--message"),
+    };
+    let args = positional;
+    if dry_run {
+        if !["add", "patch_all", "remove", "remove_all"].contains(&args[2].as_str()) {
+            return Err(anyhow!("patch {} doesn't support --dry-run", args[2]));
+        }
+        ws.dry_run()?;
+    }
+    // Only the commands reading the dependency graph need Cargo.lock.
+    if ["list", "status", "add", "patch_all", "fetch", "sync"].contains(&args[2].as_str()) {
+        ensure_lockfile(&ws)?;
+    }
+
+    match args[2].as_str() {
+        "list" => check_patch_table(&ws, registry)?,
+        "status" => status::status(&ws)?,
+        "add" => {
+            let patch_name = args
+                .get(3)
+                .ok_or_else(|| anyhow!("usage: kbuild patch add <name>"))?;
+
+            // Check if the patch name is available
+            let patch_table = ws.get_patch_table(true)?;
+            let patch = find_patch(&patch_table, patch_name)?;
+
+            let mut tx = Transaction::begin(&ws)?;
+            let source = patch.source.as_ref().unwrap();
+            if is_registry_source(source) {
+                if from.is_some() || rev.is_some() {
+                    return Err(anyhow!(
+                        "{} is from the registry, --from and --rev are only supported for git crates",
+                        patch.name
+                    ));
+                }
+                do_patch_registry(
+                    &ws,
+                    &mut tx,
+                    &patch.name,
+                    &patch.version,
+                    source,
+                    git_init,
+                    &config,
+                )?;
+            } else {
+                // Get the patch info from the specific package
+                let package = PatchPackage::from_package(&ws, patch)?;
+                match (&from, &rev) {
+                    (None, None) => do_patch(&ws, &mut tx, &package, &options, &config)?,
+                    (url, rev) => {
+                        let fork = Fork {
+                            url: url.clone(),
+                            reference: rev
+                                .clone()
+                                .map_or(GitReference::DefaultBranch, GitReference::Rev),
+                        };
+                        fork::do_patch_fork(&ws, &mut tx, &package, &fork, &options, &config)?;
+                    }
+                }
+            }
+            tx.commit()?;
+        }
+        "patch_all" => {
+            // A crate is patched if the patch table has it for the same git, or the
+            // crate would be checked out to a patched path.
+            let patched_table = ws.get_patched_table()?;
+            let patched_names: HashSet<(&str, &str)> = patched_table
+                .iter()
+                .map(|x| (x.git.as_str(), x.name.as_str()))
+                .collect();
+            let patched_paths: HashSet<&str> =
+                patched_table.iter().map(|x| x.local.as_str()).collect();
+            let selected = |name: &str| {
+                only.as_deref().is_none_or(|globs| match_globs(globs, name))
+                    && !exclude
+                        .as_deref()
+                        .is_some_and(|globs| match_globs(globs, name))
+            };
+            // The patched crates have path sources in Cargo.lock, so they are
+            // listed from the patch table.
+            let mut skipped: Vec<String> = patched_table
+                .iter()
+                .map(|x| x.name.clone())
+                .filter(|name| selected(name))
+                .collect();
+            let mut packages = Vec::new();
+            for patch in ws.get_patch_table(false)? {
+                if !selected(&patch.name) {
+                    continue;
+                }
+                let package = PatchPackage::from_package(&ws, &patch)?;
+                let (_, checkout) = clone_target(&package, options.https, &config);
+                let local = match package.sub_path.as_deref() {
+                    None | Some("") => checkout,
+                    Some(sub_path) => format!("{checkout}/{sub_path}"),
+                };
+                let patched = patched_names
+                    .contains(&(package.git.as_str(), package.name.as_str()))
+                    || patched_paths.contains(local.as_str());
+                match patched {
+                    true => skipped.push(package.name),
+                    false => packages.push(package),
+                }
+            }
+            skipped.sort();
+            skipped.dedup();
+            let mut tx = Transaction::begin(&ws)?;
+            if dry_run {
+                for package in &packages {
+                    do_patch(&ws, &mut tx, package, &options, &config)?;
+                }
+                tx.commit()?;
+            } else {
+                let failed =
+                    patch_all::patch_all(&ws, &mut tx, &packages, &skipped, &options, &config)?;
+                tx.commit()?;
+                if failed > 0 {
+                    return Err(anyhow!("{failed} packages can't be patched"));
+                }
+            }
+        }
+        "remove" => {
+            let patch_name = args
+                .get(3)
+                .ok_or_else(|| anyhow!("usage: kbuild patch remove <name>"))?;
+            let patched = find_patched(&ws, patch_name)?;
+            let mut tx = Transaction::begin(&ws)?;
+            remove_patches(&ws, &mut tx, &[patched], force, &config)?;
+            tx.commit()?;
+        }
+        "remove_all" => {
+            let mut tx = Transaction::begin(&ws)?;
+            remove_patches(&ws, &mut tx, &ws.get_patched_table()?, force, &config)?;
+            tx.commit()?;
+        }
+        "update" => {
+            let patches = match (args.get(3), all) {
+                (Some(patch_name), _) => vec![find_patched(&ws, patch_name)?],
+                (None, true) => ws.get_patched_table()?,
+                (None, false) => {
+                    return Err(anyhow!(
+                        "usage: kbuild patch update <name|--all> [--rev <rev>]"
+                    ))
+                }
+            };
+            update::update(&ws, &patches, rev.as_deref())?;
+        }
+        "export" => {
+            let patch_name = args
+                .get(3)
+                .ok_or(anyhow!("usage: kbuild patch export <name>"))?;
+            series::export(&ws, &find_patched(&ws, patch_name)?)?;
+        }
+        "push" => {
+            let patch_name = args.get(3).ok_or(anyhow!(
+                "usage: kbuild patch push <name> --remote <url> --branch <branch>"
+            ))?;
+            push::push(&ws, &find_patched(&ws, patch_name)?, &push_options)?;
+        }
+        "apply" => match args.get(3) {
+            Some(patch_name) => series::apply(&ws, &find_patched(&ws, patch_name)?)?,
+            None => {
+                for patched in ws.get_patched_table()? {
+                    series::apply(&ws, &patched)?;
+                }
+            }
+        },
+        "sync" => {
+            let mut tx = Transaction::begin(&ws)?;
+            let failed = sync::sync(&ws, &mut tx, &config, &options, git_init, yes)?;
+            tx.commit()?;
+            if failed > 0 {
+                return Err(anyhow!("{failed} crates can't be patched"));
+            }
+        }
+        "fetch" => {
+            let mirrors = match all {
+                true => mirror::cached_mirrors()?,
+                false => {
+                    let mut mirrors = Vec::new();
+                    for patch in ws.get_patch_table(false)? {
+                        let package = PatchPackage::from_package(&ws, &patch)?;
+                        let (url, _) = clone_target(&package, options.https, &config);
+                        let dir = mirror::mirror_dir(&package.git)?;
+                        if !mirrors.iter().any(|(x, _)| *x == dir) {
+                            mirrors.push((dir, url));
+                        }
+                    }
+                    mirrors
+                }
+            };
+            mirror::fetch(&mirrors)?;
+        }
+        _ => {}
+    }
+
+    let changes_deps = [
+        "add",
+        "patch_all",
+        "remove",
+        "remove_all",
+        "update",
+        "apply",
+        "sync",
+    ];
+    if let Some(bin) = check {
+        if changes_deps.contains(&args[2].as_str()) {
+            check_bin(&ws, config_file, &bin)?;
+        }
+    }
+    Ok(())
+}
+
+// submit the command to CommandAndHandler Iterator.
+inventory::submit! {
+    CommandAndHandler::new("patch", "Download crate from git and patch in Cargo.toml.", handler)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn decode_the_percent_encoding() {
+        assert_eq!(percent_decode("polyhal"), "polyhal");
+        assert_eq!(percent_decode("fix%2Firq"), "fix/irq");
+        assert_eq!(percent_decode("a%20b%25c"), "a b%c");
+        assert_eq!(percent_decode("%e4%bd%a0"), "你");
+        // The invalid escapes are kept.
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+    }
+
+    #[test]
+    fn convert_https_to_ssh() {
+        let mut config = PatchConfig::default();
+        assert_eq!(
+            git_https_to_ssh("https://github.com/Byte-OS/polyhal.git", &config),
+            "git@github.com:Byte-OS/polyhal.git"
+        );
+        assert_eq!(
+            git_https_to_ssh("http://gitee.com/x/y", &config),
+            "git@gitee.com:x/y"
+        );
+        config.ssh = HashMap::from([(
+            String::from("git.example.com"),
+            String::from("ssh://git@git.example.com:2222/"),
+        )]);
+        assert_eq!(
+            git_https_to_ssh("https://git.example.com/group/polyhal", &config),
+            "ssh://git@git.example.com:2222/group/polyhal"
+        );
+        assert_eq!(
+            git_https_to_ssh("https://github.com/x/y", &config),
+            "git@github.com:x/y"
+        );
+        for url in [
+            "ssh://git@github.com/x/y.git",
+            "git@github.com:x/y.git",
+            "file:///tmp/y.git",
+        ] {
+            assert_eq!(git_https_to_ssh(url, &config), url);
+        }
+    }
+
+    #[test]
+    fn match_the_globs() {
+        assert!(match_globs("polyhal*", "polyhal-boot"));
+        assert!(match_globs("foo, polyhal", "polyhal"));
+        assert!(!match_globs("foo,bar", "polyhal"));
+    }
+
+    #[test]
+    fn get_the_registry_cache_prefixes() {
+        assert_eq!(
+            registry_cache_prefixes(CRATES_IO),
+            ["index.crates.io-", "github.com-"]
+        );
+        assert_eq!(
+            registry_cache_prefixes("sparse+https://mirrors.ustc.edu.cn/crates.io-index/"),
+            ["mirrors.ustc.edu.cn-"]
+        );
+        assert_eq!(
+            registry_cache_prefixes("registry+https://example.com/index"),
+            ["example.com-"]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+};
+
+use anyhow::Result;
+use color_print::cprintln;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use super::{
+    add_entry, check_origin, checkout_existing, checkout_locked, clone_target, git::Git, mirror,
+    reuse_checkout, transaction::Transaction, workspace::Workspace, CloneOptions, PatchPackage,
+};
+use crate::config::PatchConfig;
+
+/// The repository to clone.
+struct CloneJob<'a> {
+    url: String,
+    dir: PathBuf,
+    /// The package used to checkout the locked commit.
+    package: &'a PatchPackage,
+    bar: ProgressBar,
+}
+
+/// Parse the progress of git like `Receiving objects:  45% (9/20)`.
+fn parse_progress(line: &str) -> Option<(&str, u64)> {
+    let line = line.strip_prefix("remote: ").unwrap_or(line);
+    let (phase, rest) = line.split_once(':')?;
+    let percent = rest.trim().split('%').next()?.trim().parse().ok()?;
+    Some((phase, percent))
+}
+
+/// Clone the repository and checkout the locked commit.
+///
+/// With `cache`, the repository is cloned from the mirror. Otherwise with
+/// `shallow`, only the tips of the branches are cloned, the locked commit is
+/// fetched if it isn't one of them.
+fn clone_locked(job: &CloneJob, options: &CloneOptions) -> Result<()> {
+    let mut progress = |line: &str| {
+        if let Some((phase, percent)) = parse_progress(line) {
+            job.bar.set_message(phase.to_string());
+            job.bar.set_position(percent);
+        }
+    };
+    if options.cache {
+        let git =
+            mirror::clone_cached(job.package, &job.url, &job.dir, false, Some(&mut progress))?;
+        return checkout_locked(&git, job.package);
+    }
+    let shallow = options.shallow;
+    let args: &[&str] = match shallow {
+        true => &["--depth", "1", "--no-single-branch", "--no-checkout"],
+        false => &["--no-checkout"],
+    };
+    let git = Git::clone_progress(&job.url, &job.dir, args, &mut progress)?;
+    let commit = &job.package.commit;
+    if shallow && !git.check(&["cat-file", "-e", &format!("{commit}^{{commit}}")]) {
+        let fetch = ["fetch", "--progress", "--depth", "1", "origin", commit];
+        // Some servers don't allow fetching the commit by hash.
+        if git.run_progress(&fetch, &mut progress).is_ok() {
+            // Deepen the branches to the locked commit, so it's connected to them.
+            let time: u64 = git
+                .output(&["log", "-1", "--format=%ct", commit])?
+                .parse()?;
+            let since = format!("--shallow-since=@{}", time.saturating_sub(1));
+            git.run_progress(&["fetch", "--progress", &since, "origin"], &mut progress)?;
+        } else {
+            let unshallow = ["fetch", "--progress", "--unshallow", "origin"];
+            git.run_progress(&unshallow, &mut progress)?;
+        }
+    }
+    checkout_locked(&git, job.package)
+}
+
+/// Run the clone jobs, at most `jobs` of them at once.
+fn run_jobs(clones: &[CloneJob], options: &CloneOptions) -> Vec<Result<()>> {
+    let queue = Mutex::new((0..clones.len()).collect::<VecDeque<_>>());
+    let results = Mutex::new((0..clones.len()).map(|_| Ok(())).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for _ in 0..options.jobs.clamp(1, clones.len().max(1)) {
+            scope.spawn(|| loop {
+                let Some(index) = queue.lock().unwrap().pop_front() else {
+                    break;
+                };
+                let job = &clones[index];
+                let result = clone_locked(job, options);
+                match result {
+                    Ok(()) => job.bar.finish_with_message("done"),
+                    Err(_) => job.bar.abandon_with_message("failed"),
+                }
+                results.lock().unwrap()[index] = result;
+            });
+        }
+    });
+    results.into_inner().unwrap()
+}
+
+/// Patch all the git packages, the repositories are cloned in parallel.
+///
+/// A package which can't be patched is reported in the summary and doesn't
+/// stop others, the number of failed packages is returned.
+pub fn patch_all(
+    ws: &Workspace,
+    tx: &mut Transaction,
+    packages: &[PatchPackage],
+    skipped: &[String],
+    options: &CloneOptions,
+    config: &PatchConfig,
+) -> Result<usize> {
+    let progress = MultiProgress::new();
+    let style = ProgressStyle::with_template("{prefix:20} [{bar:30}] {pos:>3}% {msg}")?
+        .progress_chars("=> ");
+
+    // The checkout of every package, the error if it's not available.
+    let mut checkouts: Vec<(String, Result<()>)> = Vec::new();
+    let mut clones: Vec<CloneJob> = Vec::new();
+    for package in packages {
+        let (url, checkout) = clone_target(package, options.https, config);
+        let dir = ws.path(&checkout);
+        let state = match reuse_checkout(tx, &dir, &checkout, &url, &package.commit) {
+            Ok(true) => Ok(()),
+            Err(err) => Err(err),
+            Ok(false) if dir.exists() => check_origin(&dir, &checkout, &package.git, &[&url])
+                .and_then(|_| checkout_existing(ws, tx, &dir, &checkout, package))
+                .map(|_| tx.reuse_dir(&dir, &url, &package.commit)),
+            Ok(false) => {
+                tx.clone_dir(&dir, &url, &package.commit)?;
+                let bar = progress.add(ProgressBar::new(100));
+                bar.set_style(style.clone());
+                bar.set_prefix(checkout.clone());
+                clones.push(CloneJob {
+                    url,
+                    dir,
+                    package,
+                    bar,
+                });
+                Ok(())
+            }
+        };
+        checkouts.push((checkout, state));
+    }
+
+    let results = run_jobs(&clones, options);
+    if !clones.is_empty() && !progress.is_hidden() {
+        println!();
+    }
+    let failed_clones: Vec<(&Path, String)> = clones
+        .iter()
+        .zip(results)
+        .filter_map(|(job, result)| Some((job.dir.as_path(), result.err()?.to_string())))
+        .collect();
+    for (dir, _) in &failed_clones {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+
+    let mut patched = Vec::new();
+    let mut failed = Vec::new();
+    for (package, (checkout, state)) in packages.iter().zip(checkouts) {
+        let clone_error = failed_clones
+            .iter()
+            .find(|(dir, _)| *dir == ws.path(&checkout))
+            .map(|(_, err)| anyhow!("{err}"));
+        let result = state
+            .and_then(|_| clone_error.map_or(Ok(()), Err))
+            .and_then(|_| add_entry(ws, tx, package, &checkout));
+        match result {
+            Ok(()) => patched.push(package.name.as_str()),
+            Err(err) => failed.push((package.name.as_str(), err)),
+        }
+    }
+
+    println!();
+    let (patched_count, patched) = (patched.len(), patched.join(", "));
+    let (skipped_count, skipped) = (skipped.len(), skipped.join(", "));
+    let failed_count = failed.len();
+    cprintln!("<green>Patched</green> {}: {}", patched_count, patched);
+    cprintln!("<yellow>Skipped</yellow> {}: {}", skipped_count, skipped);
+    cprintln!("<red>Failed</red>  {}", failed_count);
+    for (name, err) in &failed {
+        cprintln!("    <red>{}</red>: {}", name, err);
+    }
+    Ok(failed_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_the_progress_of_git() {
+        assert_eq!(
+            parse_progress("Receiving objects:  45% (9/20)"),
+            Some(("Receiving objects", 45))
+        );
+        assert_eq!(
+            parse_progress("remote: Counting objects: 100% (20/20), done."),
+            Some(("Counting objects", 100))
+        );
+        assert_eq!(parse_progress("Cloning into 'crates/polyhal'..."), None);
+        assert_eq!(
+            parse_progress("remote: Enumerating objects: 20, done."),
+            None
+        );
+    }
+}
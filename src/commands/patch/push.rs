@@ -0,0 +1,187 @@
+use std::path::Path;
+
+use anyhow::Result;
+use color_print::cprintln;
+
+use super::{
+    fork::{FORK_REMOTE, UPSTREAM_REMOTE},
+    git::Git,
+    workspace::{PatchedPackage, Workspace},
+};
+
+/// The options of `patch push`.
+#[derive(Debug, Clone, Default)]
+pub struct PushOptions {
+    /// The url of the fork, the `fork` remote of the checkout is used if it's not set,
+    /// or `origin` if the checkout is cloned from the fork.
+    pub remote: Option<String>,
+    /// The branch pushed to, the current branch is used if it's not set.
+    pub branch: Option<String>,
+    /// The message of the commit for the uncommitted changes.
+    pub message: Option<String>,
+}
+
+/// Convert the remote to the url cargo accepts, the scp-like `git@host:path`
+/// becomes `ssh://git@host/path` and a local path becomes `file://<path>`.
+fn cargo_git_url(remote: &str) -> String {
+    if remote.contains("://") {
+        return remote.to_string();
+    }
+    if Path::new(remote).is_absolute() {
+        return format!("file://{remote}");
+    }
+    match remote.split_once(':') {
+        Some((host, path)) if !host.is_empty() && !host.contains('/') => {
+            format!("ssh://{host}/{}", path.trim_start_matches('/'))
+        }
+        _ => remote.to_string(),
+    }
+}
+
+/// Commit the changes of the patched crate, push them to the fork and use the
+/// pushed commit in the workspace dependencies.
+///
+/// After that the patch is not needed and can be removed.
+pub fn push(ws: &Workspace, patched: &PatchedPackage, options: &PushOptions) -> Result<()> {
+    let git = Git::new(ws.checkout_root(&patched.local));
+    if !git.dir.join(".git").exists() {
+        return Err(anyhow!("{} is not a git repository", patched.local));
+    }
+    if let Some(url) = &options.remote {
+        git.set_remote(FORK_REMOTE, url)?;
+    }
+    // The checkout cloned from a fork has the fork as origin.
+    let remote = match git.check(&["remote", "get-url", FORK_REMOTE]) {
+        false if git.check(&["remote", "get-url", UPSTREAM_REMOTE]) => "origin",
+        _ => FORK_REMOTE,
+    };
+    let fork = git
+        .output(&["remote", "get-url", remote])
+        .map_err(|_| anyhow!("{} has no fork remote, use --remote <url>", patched.name))?;
+
+    if !git.status()?.is_empty() {
+        let message = options
+            .message
+            .clone()
+            .unwrap_or(format!("Update {}", patched.name));
+        git.run(&["add", "-A"])?;
+        git.run(&["commit", "-q", "-m", &message])?;
+    }
+    let branch = match &options.branch {
+        Some(branch) => branch.clone(),
+        None => git.output(&["symbolic-ref", "--short", "HEAD"])?,
+    };
+    git.run(&["push", remote, &format!("HEAD:refs/heads/{branch}")])?;
+    let rev = git.head()?;
+    cprintln!(
+        "<green>Pushed {} to {} {} at {}</green>",
+        patched.name,
+        fork,
+        branch,
+        rev
+    );
+
+    let url = cargo_git_url(&fork);
+    let manifests = ws.rewrite_git_dependency(&patched.name, &patched.git, &url, &rev)?;
+    if manifests.is_empty() {
+        cprintln!(
+            "<yellow>No dependency declares {} from {}</yellow>",
+            patched.name,
+            patched.git
+        );
+    }
+    for manifest in manifests {
+        cprintln!("<green>Updated {}</green>", manifest.display());
+    }
+    println!(
+        "The patch can be removed by `kbuild patch remove {}`",
+        patched.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    #[test]
+    fn convert_the_remote_to_cargo_url() {
+        assert_eq!(
+            cargo_git_url("git@github.com:me/polyhal.git"),
+            "ssh://git@github.com/me/polyhal.git"
+        );
+        assert_eq!(
+            cargo_git_url("git@github.com:/me/polyhal.git"),
+            "ssh://git@github.com/me/polyhal.git"
+        );
+        assert_eq!(cargo_git_url("/tmp/x.git"), "file:///tmp/x.git");
+        for url in [
+            "https://github.com/me/polyhal.git",
+            "ssh://git@git.example.com:2222/me/polyhal",
+            "file:///tmp/x.git",
+        ] {
+            assert_eq!(cargo_git_url(url), url);
+        }
+    }
+
+    /// Push the patched crate to a local bare repository, no network is needed.
+    #[test]
+    fn push_to_the_local_fork() -> Result<()> {
+        let dir = env::temp_dir().join(format!("kbuild-push-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let fork = dir.join("poly.git");
+        Git::new(&dir).run(&["init", "-q", "--bare", &fork.to_string_lossy()])?;
+
+        let root = dir.join("app");
+        fs::create_dir_all(root.join("src"))?;
+        fs::write(root.join("src/main.rs"), "fn main() {}\n")?;
+        fs::write(
+            root.join("Cargo.toml"),
+            r#"[package]
+name = "app"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+poly = { git = "https://github.com/x/poly", branch = "main" }
+"#,
+        )?;
+        let checkout = root.join("crates/poly");
+        fs::create_dir_all(&checkout)?;
+        let git = Git::new(&checkout);
+        git.run(&["init", "-q", "-b", "main"])?;
+        git.run(&["config", "user.name", "kbuild"])?;
+        git.run(&["config", "user.email", "kbuild@localhost"])?;
+        fs::write(checkout.join("lib.rs"), "")?;
+        git.run(&["add", "-A"])?;
+        git.run(&["commit", "-q", "-m", "Init"])?;
+        // The uncommitted changes are committed before pushing.
+        fs::write(checkout.join("lib.rs"), "pub fn irq() {}\n")?;
+
+        let ws = Workspace::new(&root);
+        let patched = PatchedPackage {
+            name: String::from("poly"),
+            git: String::from("https://github.com/x/poly"),
+            local: String::from("crates/poly"),
+        };
+        let options = PushOptions {
+            remote: Some(fork.to_string_lossy().to_string()),
+            branch: Some(String::from("irq")),
+            message: Some(String::from("Fix irq")),
+        };
+        push(&ws, &patched, &options)?;
+
+        let head = git.head()?;
+        assert!(git.status()?.is_empty());
+        assert_eq!(git.output(&["log", "-1", "--format=%s"])?, "Fix irq");
+        assert_eq!(Git::new(&fork).output(&["rev-parse", "irq"])?, head);
+        let manifest = fs::read_to_string(root.join("Cargo.toml"))?;
+        let url = format!("file://{}", fork.display());
+        assert!(manifest.contains(&format!(r#"poly = {{ git = "{url}", rev = "{head}" }}"#)));
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use color_print::cprintln;
+
+use super::{
+    git::Git,
+    workspace::{PatchedPackage, Workspace},
+};
+
+/// The file name of the uncommitted changes in the patch series.
+const UNCOMMITTED: &str = "uncommitted-changes.patch";
+
+/// Get the directory of the patch series, `patches/<name>` in the workspace root.
+fn series_dir(ws: &Workspace, name: &str) -> PathBuf {
+    ws.path("patches").join(name)
+}
+
+/// Get the checkout of the patched crate and the pathspec of the crate in it.
+pub fn checkout_of(ws: &Workspace, patched: &PatchedPackage) -> Result<(Git, String)> {
+    let root = ws.checkout_root(&patched.local);
+    if !root.join(".git").exists() {
+        return Err(anyhow!(
+            "{} is not a git repository, patch it with --git-init to track the changes",
+            patched.local
+        ));
+    }
+    let sub_path = ws.path(&patched.local).strip_prefix(&root)?.to_path_buf();
+    let pathspec = match sub_path.as_os_str().is_empty() {
+        true => String::from("."),
+        false => sub_path.to_string_lossy().to_string(),
+    };
+    Ok((Git::new(root), pathspec))
+}
+
+/// Save the changes of the patched crate since the locked commit into `patches/<name>`.
+///
+/// Every commit is saved by `git format-patch`, the uncommitted changes are
+/// saved as the last patch.
+pub fn export(ws: &Workspace, patched: &PatchedPackage) -> Result<()> {
+    let (git, pathspec) = checkout_of(ws, patched)?;
+    let locked = git
+        .locked()
+        .map_err(|_| anyhow!("can't find the locked commit of {}", patched.name))?;
+
+    let dir = series_dir(ws, &patched.name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::create_dir_all(&dir)?;
+    let dir_str = dir.to_string_lossy().to_string();
+    let range = format!("{locked}..HEAD");
+    let files = git.output(&["format-patch", "-o", &dir_str, &range, "--", &pathspec])?;
+    let mut count = files.lines().filter(|x| !x.is_empty()).count();
+
+    // Make the untracked files visible to git diff, in a copy of the index so
+    // the index of the user is kept.
+    let index = git
+        .dir
+        .join(git.output(&["rev-parse", "--git-path", "index"])?);
+    let temp_index = index.with_file_name("kbuild-export.index");
+    if index.exists() {
+        fs::copy(&index, &temp_index)?;
+    }
+    let temp_git = git.clone().index(&temp_index);
+    let uncommitted = dir.join(format!("{:04}-{UNCOMMITTED}", count + 1));
+    let output = format!("--output={}", uncommitted.display());
+    let diff = temp_git
+        .output(&["add", "-N", "--", &pathspec])
+        .and_then(|_| temp_git.output(&["diff", "--binary", &output, "HEAD", "--", &pathspec]));
+    if temp_index.exists() {
+        fs::remove_file(&temp_index)?;
+    }
+    diff?;
+    match fs::metadata(&uncommitted)?.len() {
+        0 => fs::remove_file(&uncommitted)?,
+        _ => count += 1,
+    }
+
+    if count == 0 {
+        fs::remove_dir(&dir)?;
+        cprintln!("<yellow>{} has no changes</yellow>", patched.name);
+    } else {
+        cprintln!(
+            "<green>Exported {} patches of {} -> patches/{}</green>",
+            count,
+            patched.name,
+            patched.name
+        );
+    }
+    Ok(())
+}
+
+/// Apply the patches in `patches/<name>` to the patched crate.
+///
+/// The patches from `git format-patch` are committed by `git am`, others are
+/// applied to the working tree by `git apply`.
+pub fn apply(ws: &Workspace, patched: &PatchedPackage) -> Result<()> {
+    let dir = series_dir(ws, &patched.name);
+    let Result::Ok(entries) = fs::read_dir(&dir) else {
+        return Ok(());
+    };
+    let mut patches: Vec<PathBuf> = entries
+        .flatten()
+        .map(|x| x.path())
+        .filter(|x| x.extension().is_some_and(|ext| ext == "patch"))
+        .collect();
+    patches.sort();
+    let (git, _) = checkout_of(ws, patched)?;
+
+    for patch in patches {
+        let content = fs::read_to_string(&patch)?;
+        let path = patch.to_string_lossy();
+        cprintln!("<green>Applying {}</green>", path);
+        if content.starts_with("From ") {
+            if let Err(err) = git.run(&["am", "-q", "--3way", &path]) {
+                git.run(&["am", "--abort"])?;
+                return Err(err);
+            }
+        } else {
+            git.run(&["apply", &path])?;
+        }
+    }
+    Ok(())
+}
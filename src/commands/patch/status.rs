@@ -0,0 +1,88 @@
+use anyhow::Result;
+use color_print::cprintln;
+
+use super::{
+    series::checkout_of,
+    workspace::{LockState, PatchedPackage, Workspace},
+};
+
+/// Get the short form of the commit.
+fn short(commit: &str) -> &str {
+    commit.get(..8).unwrap_or(commit)
+}
+
+/// Print the state of the patched crate.
+///
+/// HEAD is compared with the commit locked when the crate was patched and
+/// with the upstream branch, the uncommitted files are counted.
+fn print_status(ws: &Workspace, patched: &PatchedPackage) -> Result<()> {
+    cprintln!(
+        "<green><bold>{:20}</bold></green> {} -> {}",
+        patched.name,
+        patched.local,
+        patched.git
+    );
+
+    match ws.lock_state(&patched.name)? {
+        Some(LockState::Patched) => {}
+        Some(LockState::Unused) => {
+            cprintln!("    <yellow>the patch is not used, check the version of the crate</yellow>")
+        }
+        Some(LockState::Missing) => {
+            cprintln!("    <red>the crate is not in Cargo.lock any more, the patch is stale</red>")
+        }
+        None => cprintln!("    <yellow>Cargo.lock is not found</yellow>"),
+    }
+
+    let Ok((git, pathspec)) = checkout_of(ws, patched) else {
+        println!("    not a git repository");
+        return Ok(());
+    };
+    let head = git.head()?;
+    match git.locked() {
+        Ok(locked) if locked == head => {
+            println!("    HEAD     {} (locked)", short(&head));
+        }
+        Ok(locked) => {
+            // Only the commits changing the crate count in a shared checkout.
+            let range = format!("{locked}..HEAD");
+            let count = git.output(&["rev-list", "--count", &range, "--", &pathspec])?;
+            let (head, locked) = (short(&head), short(&locked));
+            cprintln!(
+                "    HEAD     {} <yellow>({} commits since locked {})</yellow>",
+                head,
+                count,
+                locked
+            );
+        }
+        Err(_) => println!("    HEAD     {} (locked commit unknown)", short(&head)),
+    }
+
+    if git.check(&["rev-parse", "@{u}"]) {
+        let upstream = git.output(&["rev-parse", "--abbrev-ref", "@{u}"])?;
+        let counts = git.output(&["rev-list", "--left-right", "--count", "HEAD...@{u}"])?;
+        let (ahead, behind) = counts.split_once('\t').unwrap_or(("0", "0"));
+        println!("    upstream {upstream}, {ahead} ahead, {behind} behind");
+    } else {
+        println!("    upstream none");
+    }
+
+    let files = git.output(&["status", "-s", "--", &pathspec])?;
+    match files.lines().count() {
+        0 => println!("    clean"),
+        count => cprintln!("    <red>{} files changed</red>", count),
+    }
+    Ok(())
+}
+
+/// Print the state of every patched crate.
+pub fn status(ws: &Workspace) -> Result<()> {
+    let patched_table = ws.get_patched_table()?;
+    if patched_table.is_empty() {
+        println!("No patched crate");
+    }
+    for patched in patched_table {
+        print_status(ws, &patched)?;
+    }
+    Ok(())
+}
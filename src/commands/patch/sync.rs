@@ -0,0 +1,140 @@
+use anyhow::Result;
+use color_print::cprintln;
+
+use super::{
+    check_clean, do_patch, do_patch_registry, find_patch,
+    fork::{do_patch_fork, Fork},
+    remove_patches,
+    transaction::Transaction,
+    workspace::{is_registry_source, Workspace},
+    CargoPackage, CloneOptions, GitReference, PatchPackage,
+};
+use crate::{
+    config::{PatchConfig, PatchCrate},
+    utils::confirm,
+};
+
+/// Patch the crate listed in the patch config.
+fn sync_crate(
+    ws: &Workspace,
+    tx: &mut Transaction,
+    patch: &CargoPackage,
+    krate: &PatchCrate,
+    options: &CloneOptions,
+    git_init: bool,
+    config: &PatchConfig,
+) -> Result<()> {
+    let source = patch.source.as_deref().unwrap_or_default();
+    if is_registry_source(source) {
+        if krate.fork.is_some() || krate.branch.is_some() {
+            return Err(anyhow!(
+                "{} is from the registry, fork and branch are only supported for git crates",
+                patch.name
+            ));
+        }
+        return do_patch_registry(
+            ws,
+            tx,
+            &patch.name,
+            &patch.version,
+            source,
+            git_init,
+            config,
+        );
+    }
+    let package = PatchPackage::from_package(ws, patch)?;
+    match (&krate.fork, &krate.branch) {
+        (None, None) => do_patch(ws, tx, &package, options, config),
+        (url, branch) => {
+            let fork = Fork {
+                url: url.clone(),
+                reference: branch
+                    .clone()
+                    .map_or(GitReference::DefaultBranch, GitReference::Branch),
+            };
+            do_patch_fork(ws, tx, &package, &fork, options, config)
+        }
+    }
+}
+
+/// Make the patched crates match the crates in the patch config.
+///
+/// The missing crates are patched, a crate which can't be patched is reported
+/// in the summary and doesn't stop others. The user is asked whether to remove
+/// every extra crate without uncommitted changes unless `yes` is set, the
+/// changed ones are kept. The number of failed crates is returned.
+pub fn sync(
+    ws: &Workspace,
+    tx: &mut Transaction,
+    config: &PatchConfig,
+    options: &CloneOptions,
+    git_init: bool,
+    yes: bool,
+) -> Result<usize> {
+    let patched_table = ws.get_patched_table()?;
+    let patch_table = ws.get_patch_table(true)?;
+    let mut patched = Vec::new();
+    let mut skipped = Vec::new();
+    let mut failed = Vec::new();
+    for (name, krate) in &config.crates {
+        if patched_table.iter().any(|x| x.name == *name) {
+            skipped.push(name.as_str());
+            continue;
+        }
+        // The directories of the failed crate are deleted, others are kept.
+        let savepoint = tx.savepoint();
+        let result = find_patch(&patch_table, name)
+            .and_then(|patch| sync_crate(ws, tx, patch, krate, options, git_init, config));
+        match result {
+            Ok(()) => patched.push(name.as_str()),
+            Err(err) => {
+                tx.rollback_to(savepoint)?;
+                failed.push((name.as_str(), err));
+            }
+        }
+    }
+
+    let mut removed = Vec::new();
+    for patched in patched_table {
+        if config.crates.contains_key(&patched.name) {
+            continue;
+        }
+        if check_clean(ws, &patched.name, &patched.local).is_err() {
+            cprintln!(
+                "<yellow>{} isn't in the config, it's kept since it has changes</yellow>",
+                patched.name
+            );
+            continue;
+        }
+        if yes
+            || confirm(
+                &format!("{} isn't in the config, remove it?", patched.name),
+                false,
+            )
+        {
+            removed.push(patched);
+        }
+    }
+    if !removed.is_empty() {
+        remove_patches(ws, tx, &removed, false, config)?;
+    }
+
+    println!();
+    let (patched_count, patched) = (patched.len(), patched.join(", "));
+    let (skipped_count, skipped) = (skipped.len(), skipped.join(", "));
+    let removed_count = removed.len();
+    let removed = removed
+        .iter()
+        .map(|x| x.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let failed_count = failed.len();
+    cprintln!("<green>Patched</green> {}: {}", patched_count, patched);
+    cprintln!("<yellow>Skipped</yellow> {}: {}", skipped_count, skipped);
+    cprintln!("<yellow>Removed</yellow> {}: {}", removed_count, removed);
+    cprintln!("<red>Failed</red>  {}", failed_count);
+    for (name, err) in &failed {
+        cprintln!("    <red>{}</red>: {}", name, err);
+    }
+    Ok(failed_count)
+}
@@ -0,0 +1,170 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use color_print::cprintln;
+
+use super::workspace::Workspace;
+use crate::utils::print_diff;
+
+/// The position in a transaction to roll back to, see [Transaction::savepoint].
+pub struct Savepoint {
+    created: usize,
+    checkouts: usize,
+}
+
+/// The changes of a patch operation on the disk.
+///
+/// The created directories are deleted, the removed directories are restored
+/// and Cargo.toml is written back if the transaction is dropped without
+/// [Transaction::commit], so a failed operation leaves nothing behind.
+///
+/// In the dry run, the directories to create or delete are printed and the
+/// diff of Cargo.toml is printed when the transaction is committed.
+pub struct Transaction<'a> {
+    ws: &'a Workspace,
+    pub dry_run: bool,
+    /// The content of Cargo.toml before the operation.
+    original: String,
+    /// The created directories and the top most missing directories of them.
+    created: Vec<(PathBuf, PathBuf)>,
+    /// The checkouts used by the operation, the urls and the revisions of them.
+    checkouts: Vec<(PathBuf, String, String)>,
+    /// The removed directories and where they are staged.
+    removed: Vec<(PathBuf, PathBuf)>,
+    committed: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn begin(ws: &'a Workspace) -> Result<Self> {
+        Ok(Transaction {
+            ws,
+            dry_run: ws.is_dry_run(),
+            original: fs::read_to_string(ws.path("Cargo.toml"))?,
+            created: Vec::new(),
+            checkouts: Vec::new(),
+            removed: Vec::new(),
+            committed: false,
+        })
+    }
+
+    /// Record the directory which is going to be created by the operation.
+    ///
+    /// The missing parent directories are recorded too, e.g. `crates`.
+    pub fn create_dir(&mut self, dir: &Path) -> Result<()> {
+        if dir.exists() {
+            return Err(anyhow!("{} exists already", dir.display()));
+        }
+        let top = dir
+            .ancestors()
+            .take_while(|x| !x.exists())
+            .last()
+            .unwrap_or(dir);
+        if self.dry_run {
+            println!("create {}", dir.display());
+        }
+        self.created.push((dir.to_path_buf(), top.to_path_buf()));
+        Ok(())
+    }
+
+    /// Record the directory where the repository is going to be cloned from
+    /// the url and checked out at the revision, see [Transaction::create_dir].
+    pub fn clone_dir(&mut self, dir: &Path, url: &str, rev: &str) -> Result<()> {
+        self.create_dir(dir)?;
+        self.reuse_dir(dir, url, rev);
+        Ok(())
+    }
+
+    /// Record the existing checkout used by the operation.
+    pub fn reuse_dir(&mut self, dir: &Path, url: &str, rev: &str) {
+        let checkout = (dir.to_path_buf(), url.to_string(), rev.to_string());
+        self.checkouts.push(checkout);
+    }
+
+    /// Get the url and the revision of the checkout used in the transaction.
+    pub fn checkout(&self, dir: &Path) -> Option<(&str, &str)> {
+        self.checkouts
+            .iter()
+            .find(|(x, _, _)| x == dir)
+            .map(|(_, url, rev)| (url.as_str(), rev.as_str()))
+    }
+
+    /// Mark the changes made so far, so the later ones can be undone alone.
+    pub fn savepoint(&self) -> Savepoint {
+        Savepoint {
+            created: self.created.len(),
+            checkouts: self.checkouts.len(),
+        }
+    }
+
+    /// Delete the directories created after the savepoint, the changes before
+    /// it are kept.
+    pub fn rollback_to(&mut self, savepoint: Savepoint) -> Result<()> {
+        self.checkouts.truncate(savepoint.checkouts);
+        for (_, dir) in self.created.drain(savepoint.created..).rev() {
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the directory aside, it's deleted when the transaction is committed.
+    pub fn remove_dir(&mut self, dir: &Path) -> Result<()> {
+        if self.dry_run {
+            println!("delete {}", dir.display());
+            return Ok(());
+        }
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let staged = dir.with_file_name(format!(".{name}.kbuild-removed"));
+        if staged.exists() {
+            fs::remove_dir_all(&staged)?;
+        }
+        fs::rename(dir, &staged)?;
+        self.removed.push((dir.to_path_buf(), staged));
+        Ok(())
+    }
+
+    /// Keep the changes, the staged directories are deleted.
+    pub fn commit(mut self) -> Result<()> {
+        self.committed = true;
+        if self.dry_run {
+            print_diff("Cargo.toml", &self.original, &self.ws.manifest_content()?);
+        }
+        for (_, staged) in &self.removed {
+            fs::remove_dir_all(staged)?;
+        }
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        let manifest = self.ws.path("Cargo.toml");
+        if fs::read_to_string(&manifest)? != self.original {
+            fs::write(&manifest, &self.original)?;
+        }
+        for (_, dir) in self.created.iter().rev() {
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+        }
+        for (dir, staged) in self.removed.iter().rev() {
+            fs::rename(staged, dir)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        // Nothing is changed in the dry run.
+        if self.committed || self.dry_run {
+            return;
+        }
+        match self.rollback() {
+            Ok(()) => cprintln!("<yellow>The changes are rolled back</yellow>"),
+            Err(err) => cprintln!("<red>Failed to roll back the changes: {}</red>", err),
+        }
+    }
+}
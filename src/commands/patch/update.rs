@@ -0,0 +1,103 @@
+use std::{path::PathBuf, process::Command};
+
+use anyhow::Result;
+use color_print::cprintln;
+
+use super::{
+    git::Git,
+    workspace::{PatchedPackage, Workspace},
+};
+use crate::utils::spawn;
+
+/// The result of updating a checkout.
+enum UpdateResult {
+    UpToDate,
+    Updated(String),
+    /// The rebase has conflicts in the files, it's aborted.
+    Conflict(String),
+    Failed(anyhow::Error),
+}
+
+/// Fetch the upstream and rebase the local commits onto `rev` (the upstream
+/// branch by default).
+fn update_checkout(git: &Git, rev: Option<&str>) -> Result<UpdateResult> {
+    git.run(&["fetch", "-q", "origin"])?;
+    let target = match rev {
+        Some(rev) => rev.to_string(),
+        None if git.check(&["rev-parse", "@{u}"]) => String::from("@{u}"),
+        None => return Err(anyhow!("no upstream branch, use --rev <rev>")),
+    };
+    let target = git.output(&["rev-parse", &format!("{target}^{{commit}}")])?;
+    if git.check(&["merge-base", "--is-ancestor", &target, "HEAD"]) {
+        return Ok(UpdateResult::UpToDate);
+    }
+
+    if git.run(&["rebase", "-q", "--autostash", &target]).is_err() {
+        let files = git.output(&["diff", "--name-only", "--diff-filter=U"])?;
+        git.run(&["rebase", "--abort"])?;
+        return Ok(UpdateResult::Conflict(files));
+    }
+    // The local commits are based on the target now, `patch export` starts from it.
+    git.set_locked(&target)?;
+    Ok(UpdateResult::Updated(git.head()?))
+}
+
+/// Update the checkouts of the patched crates, then update Cargo.lock for them.
+///
+/// A failed crate is reported and doesn't stop others.
+pub fn update(ws: &Workspace, patches: &[PatchedPackage], rev: Option<&str>) -> Result<()> {
+    // Crates from the same repository are updated together.
+    let mut checkouts: Vec<(PathBuf, Vec<&str>)> = Vec::new();
+    for patched in patches {
+        let root = ws.checkout_root(&patched.local);
+        match checkouts.iter_mut().find(|(x, _)| *x == root) {
+            Some((_, names)) => names.push(&patched.name),
+            None => checkouts.push((root, vec![&patched.name])),
+        }
+    }
+
+    let mut updated = Vec::new();
+    let mut failed = 0;
+    for (root, names) in checkouts {
+        let names_str = names.join(", ");
+        cprintln!("<green>Updating {}</green>", names_str);
+        let result = match root.join(".git").exists() {
+            true => update_checkout(&Git::new(&root), rev).unwrap_or_else(UpdateResult::Failed),
+            false => UpdateResult::Failed(anyhow!("{} is not a git repository", root.display())),
+        };
+        match result {
+            UpdateResult::UpToDate => println!("    up to date"),
+            UpdateResult::Updated(head) => {
+                println!("    rebased to {head}");
+                updated.extend(names);
+            }
+            UpdateResult::Conflict(files) => {
+                failed += 1;
+                cprintln!("    <red>conflicts, the rebase is aborted</red>");
+                for file in files.lines() {
+                    cprintln!("    <red>    {}</red>", file);
+                }
+            }
+            UpdateResult::Failed(err) => {
+                failed += 1;
+                cprintln!("    <red>{}</red>", err);
+            }
+        }
+    }
+
+    if !updated.is_empty() {
+        let mut command = Command::new("cargo");
+        command.arg("update").current_dir(&ws.root);
+        for name in &updated {
+            command.args(["-p", name]);
+        }
+        let exit_status = spawn(&mut command)?.wait()?;
+        if !exit_status.success() {
+            return Err(anyhow!("cargo update failed, {exit_status}"));
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{failed} repositories are not updated"));
+    }
+    Ok(())
+}
@@ -0,0 +1,571 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    collections::{BTreeSet, HashMap, VecDeque},
+    env::current_dir,
+    fs,
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::Result;
+use json::JsonValue;
+use toml::Table;
+use toml_edit::{DocumentMut, InlineTable, TableLike};
+
+use super::GitReference;
+use crate::utils::spawn;
+
+/// Resolve `.` and `..` in the path without reading the disk.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The package in the dependency graph from `cargo metadata`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CargoPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    pub manifest_path: PathBuf,
+    /// The workspace members which depend on this package directly.
+    pub direct: BTreeSet<String>,
+    /// The workspace members which depend on this package through other packages.
+    pub transitive: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PatchedPackage {
+    pub name: String,
+    pub git: String,
+    /// The path in the patch table, relative to the workspace root.
+    pub local: String,
+}
+
+/// The state of the patched crate in Cargo.lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    /// The crate is locked to the patched path.
+    Patched,
+    /// The crate is in Cargo.lock, but the patch is not used (e.g. the version is not matched).
+    Unused,
+    /// The crate has left Cargo.lock, the patch is stale.
+    Missing,
+}
+
+/// Check if the source is a registry (crates.io or others).
+pub fn is_registry_source(source: &str) -> bool {
+    source.starts_with("registry+") || source.starts_with("sparse+")
+}
+
+/// The dependency tables in the manifest.
+const DEPENDENCY_TABLES: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Check if the git urls point to the same repository.
+fn same_git(a: &str, b: &str) -> bool {
+    let normalize = |x: &str| {
+        let x = x.trim_end_matches('/');
+        x.strip_suffix(".git").unwrap_or(x).to_string()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Check if the dependency `key` declares the crate `name` from `git`.
+fn declares(key: &str, dep: &dyn TableLike, name: &str, git: &str) -> bool {
+    let package = dep.get("package").and_then(|x| x.as_str());
+    package.unwrap_or(key) == name
+        && dep
+            .get("git")
+            .and_then(|x| x.as_str())
+            .is_some_and(|x| same_git(x, git))
+}
+
+/// Get the dependency tables in the manifest, including `[workspace.dependencies]`
+/// and the target specific dependencies.
+fn dependency_tables(document: &mut DocumentMut) -> Vec<&mut dyn TableLike> {
+    let mut tables: Vec<&mut dyn TableLike> = Vec::new();
+    for (key, item) in document.as_table_mut().iter_mut() {
+        match key.get() {
+            "target" => {
+                let Some(targets) = item.as_table_like_mut() else {
+                    continue;
+                };
+                for (_, target) in targets.iter_mut() {
+                    let Some(target) = target.as_table_like_mut() else {
+                        continue;
+                    };
+                    for (key, deps) in target.iter_mut() {
+                        if DEPENDENCY_TABLES.contains(&key.get()) {
+                            tables.extend(deps.as_table_like_mut());
+                        }
+                    }
+                }
+            }
+            "workspace" => {
+                tables.extend(
+                    item.get_mut("dependencies")
+                        .and_then(|x| x.as_table_like_mut()),
+                );
+            }
+            key if DEPENDENCY_TABLES.contains(&key) => {
+                tables.extend(item.as_table_like_mut());
+            }
+            _ => {}
+        }
+    }
+    tables
+}
+
+/// Change the dependency `name` from `git` to the `rev` of `new_git` in the table.
+fn rewrite_dependencies(
+    table: &mut dyn TableLike,
+    name: &str,
+    git: &str,
+    new_git: &str,
+    rev: &str,
+) -> bool {
+    let mut changed = false;
+    for (key, dep) in table.iter_mut() {
+        let Some(dep) = dep.as_table_like_mut() else {
+            continue;
+        };
+        if declares(key.get(), dep, name, git) {
+            dep.insert("git", toml_edit::value(new_git));
+            dep.remove("branch");
+            dep.remove("tag");
+            dep.insert("rev", toml_edit::value(rev));
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The cargo workspace which the patch command works on.
+///
+/// The `[patch]` table is in the root manifest and the patch directory is
+/// relative to the root, so the command can be run from a member crate.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    pub root: PathBuf,
+    /// Cargo.toml edited in the memory in the dry run.
+    staged: Option<RefCell<String>>,
+    /// The manifests of the root and the members, read once.
+    members: OnceCell<Vec<PathBuf>>,
+}
+
+impl Workspace {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Workspace {
+            root: root.into(),
+            staged: None,
+            members: OnceCell::new(),
+        }
+    }
+
+    /// Find the workspace root through `cargo locate-project`.
+    ///
+    /// If cargo can't locate it, the top most `Cargo.toml` with `[workspace]`
+    /// (or the nearest `Cargo.toml`) in the parent directories is used.
+    pub fn locate() -> Result<Self> {
+        let outputs = spawn(
+            Command::new("cargo")
+                .args(["locate-project", "--workspace", "--message-format", "plain"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+        )?
+        .wait_with_output()?;
+        if outputs.status.success() {
+            let manifest = PathBuf::from(String::from_utf8(outputs.stdout)?.trim());
+            if let Some(root) = manifest.parent() {
+                return Ok(Workspace::new(root));
+            }
+        }
+
+        let cwd = current_dir()?;
+        let mut root = None;
+        for dir in cwd.ancestors() {
+            let manifest = dir.join("Cargo.toml");
+            let Ok(content) = fs::read_to_string(&manifest) else {
+                continue;
+            };
+            let is_workspace = content
+                .parse::<Table>()
+                .is_ok_and(|x| x.contains_key("workspace"));
+            if is_workspace || root.is_none() {
+                root = Some(dir.to_path_buf());
+            }
+        }
+        root.map(Workspace::new)
+            .ok_or(anyhow!("can't find Cargo.toml in {}", cwd.display()))
+    }
+
+    /// Get the path relative to the workspace root.
+    pub fn path(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Check if the path is below the directory, which is relative to the
+    /// workspace root. The paths are compared after resolving `..`.
+    pub fn is_below(&self, dir: impl AsRef<Path>, path: &Path) -> bool {
+        let dir = normalize(&self.path(dir));
+        let path = normalize(path);
+        path != dir && path.starts_with(dir)
+    }
+
+    /// Get the git checkout which the patched crate lives in.
+    ///
+    /// The crate may be in a sub-path of a repository, the nearest directory
+    /// with `.git` below the workspace root is used, or the crate itself.
+    pub fn checkout_root(&self, local: &str) -> PathBuf {
+        let path = normalize(&self.path(local));
+        path.ancestors()
+            .take_while(|dir| *dir != self.root && dir.starts_with(&self.root))
+            .find(|dir| dir.join(".git").exists())
+            .unwrap_or(&path)
+            .to_path_buf()
+    }
+
+    fn manifest(&self) -> PathBuf {
+        self.path("Cargo.toml")
+    }
+
+    /// Edit Cargo.toml in the memory, the file is not changed.
+    pub fn dry_run(&mut self) -> Result<()> {
+        self.staged = Some(RefCell::new(fs::read_to_string(self.manifest())?));
+        Ok(())
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.staged.is_some()
+    }
+
+    /// Get the content of Cargo.toml, including the changes in the dry run.
+    pub fn manifest_content(&self) -> Result<String> {
+        match &self.staged {
+            Some(staged) => Ok(staged.borrow().clone()),
+            None => Ok(fs::read_to_string(self.manifest())?),
+        }
+    }
+
+    fn write_manifest(&self, content: String) -> Result<()> {
+        match &self.staged {
+            Some(staged) => *staged.borrow_mut() = content,
+            None => fs::write(self.manifest(), content)?,
+        }
+        Ok(())
+    }
+
+    /// Get the dependency graph through `cargo metadata`.
+    fn metadata(&self) -> Result<JsonValue> {
+        self.cargo_metadata(&[])
+    }
+
+    /// Run `cargo metadata`, Cargo.lock isn't written in the dry run.
+    fn cargo_metadata(&self, args: &[&str]) -> Result<JsonValue> {
+        let mut command = Command::new("cargo");
+        command
+            .args(["metadata", "--format-version", "1"])
+            .args(args)
+            .current_dir(&self.root)
+            .stdout(Stdio::piped());
+        if self.is_dry_run() {
+            command.arg("--locked");
+        }
+        let outputs = spawn(&mut command)?.wait_with_output()?;
+        if !outputs.status.success() {
+            return Err(anyhow!("cargo metadata failed, {}", outputs.status));
+        }
+        Ok(json::parse(&String::from_utf8(outputs.stdout)?)?)
+    }
+
+    /// Get the packages can be patched, registry packages are included if `registry` is true.
+    ///
+    /// The packages are sorted by name and version, every package records the
+    /// workspace members depend on it.
+    pub fn get_patch_table(&self, registry: bool) -> Result<Vec<CargoPackage>> {
+        let metadata = self.metadata()?;
+        let mut packages: HashMap<&str, CargoPackage> = HashMap::new();
+        for package in metadata["packages"].members() {
+            let Some(id) = package["id"].as_str() else {
+                continue;
+            };
+            packages.insert(
+                id,
+                CargoPackage {
+                    name: package["name"].as_str().unwrap_or_default().to_string(),
+                    version: package["version"].as_str().unwrap_or_default().to_string(),
+                    source: package["source"].as_str().map(String::from),
+                    manifest_path: PathBuf::from(
+                        package["manifest_path"].as_str().unwrap_or_default(),
+                    ),
+                    direct: BTreeSet::new(),
+                    transitive: BTreeSet::new(),
+                },
+            );
+        }
+
+        // package id -> the ids of its dependencies
+        let graph: HashMap<&str, Vec<&str>> = metadata["resolve"]["nodes"]
+            .members()
+            .filter_map(|node| {
+                let deps = node["dependencies"].members().filter_map(|x| x.as_str());
+                Some((node["id"].as_str()?, deps.collect()))
+            })
+            .collect();
+
+        // Walk the graph from every workspace member.
+        for member in metadata["workspace_members"]
+            .members()
+            .filter_map(|x| x.as_str())
+        {
+            let Some(member_name) = packages.get(member).map(|x| x.name.clone()) else {
+                continue;
+            };
+            let direct = graph.get(member).cloned().unwrap_or_default();
+            let mut visited = BTreeSet::from([member]);
+            let mut queue: VecDeque<&str> = direct.iter().copied().collect();
+            while let Some(id) = queue.pop_front() {
+                if !visited.insert(id) {
+                    continue;
+                }
+                if let Some(package) = packages.get_mut(id) {
+                    match direct.contains(&id) {
+                        true => package.direct.insert(member_name.clone()),
+                        false => package.transitive.insert(member_name.clone()),
+                    };
+                }
+                queue.extend(graph.get(id).into_iter().flatten());
+            }
+        }
+
+        let mut patch_table: Vec<CargoPackage> = packages
+            .into_values()
+            .filter(|x| match x.source {
+                Some(ref source) => {
+                    source.starts_with("git+") || (registry && is_registry_source(source))
+                }
+                None => false,
+            })
+            .collect();
+        patch_table.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        Ok(patch_table)
+    }
+
+    /// Get the manifests of the workspace root and the members.
+    ///
+    /// The dependencies aren't resolved, so Cargo.lock isn't touched.
+    pub fn member_manifests(&self) -> Result<Vec<PathBuf>> {
+        if let Some(manifests) = self.members.get() {
+            return Ok(manifests.clone());
+        }
+        let metadata = self.cargo_metadata(&["--no-deps"])?;
+        let members: BTreeSet<&str> = metadata["workspace_members"]
+            .members()
+            .filter_map(|x| x.as_str())
+            .collect();
+        let mut manifests = vec![self.manifest()];
+        for package in metadata["packages"].members() {
+            if !package["id"]
+                .as_str()
+                .is_some_and(|id| members.contains(id))
+            {
+                continue;
+            }
+            let manifest = PathBuf::from(package["manifest_path"].as_str().unwrap_or_default());
+            if !manifests.contains(&manifest) {
+                manifests.push(manifest);
+            }
+        }
+        Ok(self.members.get_or_init(|| manifests).clone())
+    }
+
+    /// Change the git dependency `name` from `git` to the `rev` of `new_git`.
+    ///
+    /// Every manifest in the workspace is changed, including `[workspace.dependencies]`
+    /// and the target specific dependencies. The changed manifests are returned.
+    pub fn rewrite_git_dependency(
+        &self,
+        name: &str,
+        git: &str,
+        new_git: &str,
+        rev: &str,
+    ) -> Result<Vec<PathBuf>> {
+        let mut changed_manifests = Vec::new();
+        for manifest in self.member_manifests()? {
+            let mut document: DocumentMut = fs::read_to_string(&manifest)?.parse()?;
+            let mut changed = false;
+            for table in dependency_tables(&mut document) {
+                changed |= rewrite_dependencies(table, name, git, new_git, rev);
+            }
+            if changed {
+                fs::write(&manifest, document.to_string())?;
+                changed_manifests.push(manifest);
+            }
+        }
+        Ok(changed_manifests)
+    }
+
+    /// Get the `branch`, `tag` or `rev` of the dependency `name` from `git`
+    /// declared in the manifests, `None` if it isn't declared by the workspace.
+    pub fn declared_reference(&self, name: &str, git: &str) -> Result<Option<GitReference>> {
+        for manifest in self.member_manifests()? {
+            let mut document: DocumentMut = fs::read_to_string(&manifest)?.parse()?;
+            for table in dependency_tables(&mut document) {
+                for (key, dep) in table.iter() {
+                    let Some(dep) = dep.as_table_like() else {
+                        continue;
+                    };
+                    if !declares(key, dep, name, git) {
+                        continue;
+                    }
+                    let value = |key: &str| dep.get(key).and_then(|x| x.as_str()).map(String::from);
+                    let reference = match (value("branch"), value("tag"), value("rev")) {
+                        (Some(branch), _, _) => GitReference::Branch(branch),
+                        (_, Some(tag), _) => GitReference::Tag(tag),
+                        (_, _, Some(rev)) => GitReference::Rev(rev),
+                        _ => GitReference::DefaultBranch,
+                    };
+                    return Ok(Some(reference));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the state of the patched crate in Cargo.lock, `None` if there is no Cargo.lock.
+    pub fn lock_state(&self, name: &str) -> Result<Option<LockState>> {
+        let Ok(content) = fs::read_to_string(self.path("Cargo.lock")) else {
+            return Ok(None);
+        };
+        let lock: Table = toml::from_str(&content)?;
+        let packages: Vec<&Table> = lock
+            .get("package")
+            .and_then(|x| x.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x.as_table())
+            .filter(|x| x.get("name").and_then(|x| x.as_str()) == Some(name))
+            .collect();
+        let state = if packages.iter().any(|x| !x.contains_key("source")) {
+            LockState::Patched
+        } else if !packages.is_empty() {
+            LockState::Unused
+        } else {
+            LockState::Missing
+        };
+        Ok(Some(state))
+    }
+
+    /// Get the packages patched with a `path` in Cargo.toml.
+    pub fn get_patched_table(&self) -> Result<Vec<PatchedPackage>> {
+        let mut res = Vec::new();
+        let cargo_toml: Table = toml::from_str(&self.manifest_content()?)?;
+        if let Some(patch_table) = cargo_toml.get("patch").and_then(|x| x.as_table()) {
+            for (git, value) in patch_table {
+                let Some(git_table) = value.as_table() else {
+                    continue;
+                };
+                for (name, detail) in git_table {
+                    if let Some(path) = detail.get("path").and_then(|x| x.as_str()) {
+                        res.push(PatchedPackage {
+                            name: name.to_string(),
+                            git: git.to_string(),
+                            local: path.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Read Cargo.toml as a format-preserving document, comments and order are kept.
+    fn read_manifest(&self) -> Result<DocumentMut> {
+        Ok(self.manifest_content()?.parse()?)
+    }
+
+    /// Add `[patch."<key>"] name = { path = "<path>" }` into Cargo.toml.
+    pub fn add_patch_entry(&self, key: &str, name: &str, path: &str) -> Result<()> {
+        let mut cargo_toml = self.read_manifest()?;
+        let patch_table = cargo_toml
+            .entry("patch")
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .ok_or(anyhow!("patch in Cargo.toml is not a table"))?;
+        // Don't generate an empty `[patch]` header.
+        patch_table.set_implicit(true);
+        let git_table = patch_table
+            .entry(key)
+            .or_insert(toml_edit::table())
+            .as_table_like_mut()
+            .ok_or(anyhow!("patch.{key} in Cargo.toml is not a table"))?;
+
+        let mut detail = InlineTable::new();
+        detail.insert("path", path.into());
+        git_table.insert(name, toml_edit::value(detail));
+        self.write_manifest(cargo_toml.to_string())
+    }
+
+    /// Remove the patches from Cargo.toml, the empty tables are removed too.
+    pub fn remove_patch_entries(&self, patches: &[PatchedPackage]) -> Result<()> {
+        let mut cargo_toml = self.read_manifest()?;
+        if let Some(patch_table) = cargo_toml
+            .get_mut("patch")
+            .and_then(|x| x.as_table_like_mut())
+        {
+            for patch in patches {
+                let key = patch.git.as_str();
+                if let Some(git_table) =
+                    patch_table.get_mut(key).and_then(|x| x.as_table_like_mut())
+                {
+                    git_table.remove(&patch.name);
+                    if git_table.is_empty() {
+                        patch_table.remove(key);
+                    }
+                }
+            }
+            if patch_table.is_empty() {
+                cargo_toml.remove("patch");
+            }
+        }
+        self.write_manifest(cargo_toml.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_the_path() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("/a/b/../../..")), Path::new("/"));
+        assert_eq!(normalize(Path::new("crates/../../x")), Path::new("x"));
+    }
+
+    #[test]
+    fn check_the_source() {
+        assert!(is_registry_source(
+            "registry+https://github.com/rust-lang/crates.io-index"
+        ));
+        assert!(is_registry_source("sparse+https://index.crates.io/"));
+        assert!(!is_registry_source("git+https://github.com/x/y#abc"));
+        assert!(same_git(
+            "https://github.com/x/y.git",
+            "https://github.com/x/y/"
+        ));
+        assert!(!same_git(
+            "https://github.com/x/y",
+            "https://github.com/x/z"
+        ));
+    }
+}
@@ -2,16 +2,15 @@ use std::process::Command;
 
 use anyhow::Result;
 
-use crate::CommandAndHandler;
+use crate::{utils::spawn, CommandAndHandler};
 
 /// The command handler of the help command.
 pub fn handler(_args: Vec<String>) -> Result<()> {
-    let mut outputs = Command::new("cargo")
-        .arg("install")
-        .arg("kbuild")
-        .spawn()
-        .expect("can't clone from git repository");
-    outputs.wait().expect("can't wait for git clone");
+    let mut outputs = spawn(Command::new("cargo").arg("install").arg("kbuild"))?;
+    let exit_status = outputs.wait()?;
+    if !exit_status.success() {
+        return Err(anyhow!("can't install kbuild, {exit_status}"));
+    }
 
     Ok(())
 }
@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    thread::sleep,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use color_print::cprintln;
+
+use crate::{
+    utils::{get_option, get_positional},
+    CommandAndHandler,
+};
+
+use super::build::{build, BuildOptions};
+
+/// The interval to check the files.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The modified time of every watched file.
+type Snapshot = HashMap<PathBuf, SystemTime>;
+
+/// Collect the files under the dir recursively.
+///
+/// Hidden files and the `target` directory are skipped.
+fn collect_files(dir: &Path, snapshot: &mut Snapshot) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with('.') || name == "target" {
+            continue;
+        }
+        match entry.metadata() {
+            Ok(meta) if meta.is_dir() => collect_files(&path, snapshot),
+            Ok(meta) => {
+                if let Ok(modified) = meta.modified() {
+                    snapshot.insert(path, modified);
+                }
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Take a snapshot of the workspace and the config file.
+fn take_snapshot(file_name: &str) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    collect_files(Path::new("."), &mut snapshot);
+    if let Ok(modified) = fs::metadata(file_name).and_then(|meta| meta.modified()) {
+        snapshot.insert(PathBuf::from(file_name), modified);
+    }
+    snapshot
+}
+
+/// Get the files that were added, changed or removed.
+fn changed_files(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = new
+        .iter()
+        .filter(|(path, modified)| old.get(*path) != Some(modified))
+        .map(|(path, _)| path.clone())
+        .collect();
+    changed.extend(old.keys().filter(|path| !new.contains_key(*path)).cloned());
+    changed
+}
+
+/// Spawn the run command in a new process group.
+fn spawn_run(run: &str) -> Option<Child> {
+    cprintln!("<green>Running</green> {}", run);
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(run);
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    match command.spawn() {
+        Ok(child) => Some(child),
+        Err(err) => {
+            cprintln!("<red>Can't run {}: {}</red>", run, err);
+            None
+        }
+    }
+}
+
+/// Stop the run command, the whole process group is killed (e.g. make and qemu).
+fn stop_run(child: &mut Child) {
+    if let Ok(Some(_)) = child.try_wait() {
+        return;
+    }
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .arg("--")
+        .arg(format!("-{}", child.id()))
+        .status();
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Build the bin, errors are printed instead of returned.
+fn rebuild(file_name: &str, bin: &str, options: &BuildOptions, run: Option<&str>) -> Option<Child> {
+    match build(file_name, bin, options) {
+        Ok(()) => {
+            cprintln!("<green><bold>Build {} finished</bold></green>", bin);
+            run.and_then(spawn_run)
+        }
+        Err(err) => {
+            cprintln!("<red><bold>Error: {}</bold></red>", err);
+            None
+        }
+    }
+}
+
+/// The handler of the command.
+fn handler(args: Vec<String>) -> Result<()> {
+    let options = BuildOptions::from_args(&args);
+    let run = get_option(&args, "--run");
+    let args = get_positional(&args, &["--run"]);
+    let file_name = match args.len() > 2 {
+        true => &args[2],
+        false => "default.toml",
+    };
+    let bin = if args.len() > 3 { &args[3] } else { "default" };
+
+    let mut snapshot = take_snapshot(file_name);
+    let mut running = rebuild(file_name, bin, &options, run.as_deref());
+    cprintln!("<green>Watching for changes...</green>");
+    loop {
+        sleep(POLL_INTERVAL);
+        let new_snapshot = take_snapshot(file_name);
+        let mut changed = changed_files(&snapshot, &new_snapshot);
+        if changed.is_empty() {
+            continue;
+        }
+        // Wait until the files stop changing, editors may write several times.
+        snapshot = new_snapshot;
+        loop {
+            sleep(POLL_INTERVAL);
+            let new_snapshot = take_snapshot(file_name);
+            let more = changed_files(&snapshot, &new_snapshot);
+            if more.is_empty() {
+                break;
+            }
+            changed.extend(more);
+            snapshot = new_snapshot;
+        }
+
+        if changed.iter().any(|path| path == Path::new(file_name)) {
+            cprintln!("<yellow>Config {} changed, reloading</yellow>", file_name);
+        } else {
+            cprintln!(
+                "<yellow>{} changed, rebuilding</yellow>",
+                changed[0].display()
+            );
+        }
+        if let Some(ref mut child) = running {
+            stop_run(child);
+        }
+        running = rebuild(file_name, bin, &options, run.as_deref());
+        // The build may generate files in the workspace, don't trigger again.
+        snapshot = take_snapshot(file_name);
+    }
+}
+
+// submit the command to CommandAndHandler Iterator.
+inventory::submit! {
+    CommandAndHandler::new("watch", "rebuild (and rerun with --run <cmd>) when files change.", handler)
+}
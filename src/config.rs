@@ -1,8 +1,14 @@
-use std::{collections::HashMap, fs};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    path::PathBuf,
+};
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use crate::error::KbuildError;
+
 /// This is a struct will be deserialized from the given filename.
 ///
 /// version indicates the version of the kernel.
@@ -15,6 +21,35 @@ pub struct KernelConfig {
     /// Config list for kernel. This field will be converted to rust cfg.
     #[serde(default)]
     pub bin: HashMap<String, BinaryConfig>,
+    /// Config of the patch command.
+    #[serde(default)]
+    pub patch: PatchConfig,
+}
+
+/// Patch configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct PatchConfig {
+    /// The directory to put the patched crates, `crates` by default.
+    pub dir: Option<String>,
+    /// Rules to convert the https url to ssh url, host -> ssh prefix.
+    ///
+    /// e.g. `"git.example.com" = "ssh://git@git.example.com:2222/"`,
+    /// the host without a rule uses `git@<host>:`.
+    #[serde(default)]
+    pub ssh: HashMap<String, String>,
+    /// The crates patched by `kbuild patch sync`, name -> where to clone it from.
+    #[serde(default)]
+    pub crates: BTreeMap<String, PatchCrate>,
+}
+
+/// The crate listed in the patch config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct PatchCrate {
+    /// The fork cloned instead of the upstream, the upstream is added as the
+    /// `upstream` remote.
+    pub fork: Option<String>,
+    /// The branch checked out instead of the locked commit.
+    pub branch: Option<String>,
 }
 
 /// Global configuration
@@ -29,6 +64,9 @@ pub struct KernelGlobalConfig {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BinaryConfig {
     pub target: String,
+    /// The name of the bin, filled by [KernelConfig::get_bin_config].
+    #[serde(skip)]
+    name: String,
     #[serde(skip)]
     global_config: KernelGlobalConfig,
     /// Build std args
@@ -40,57 +78,155 @@ pub struct BinaryConfig {
 }
 
 impl BinaryConfig {
-    pub fn get_configs(&self) -> HashMap<String, String> {
-        let mut configs = self.global_config.configs.clone();
+    pub fn get_configs(&self) -> BTreeMap<String, String> {
+        let mut configs: BTreeMap<_, _> = self.global_config.configs.clone().into_iter().collect();
         configs.extend(self.configs.clone());
         configs
     }
 
-    pub fn get_envs(&self) -> HashMap<String, String> {
-        let mut envs = self.global_config.env.clone();
+    pub fn get_envs(&self) -> BTreeMap<String, String> {
+        let mut envs: BTreeMap<_, _> = self.global_config.env.clone().into_iter().collect();
         envs.extend(self.env.clone());
         envs
     }
 
+    /// Get the rustflags passed to cargo.
+    ///
+    /// The configs are sorted, so the same config always produces the same
+    /// rustflags and cargo doesn't rebuild because of the order.
+    pub fn get_rustflags(&self) -> Vec<String> {
+        let mut rustflags = vec![
+            String::from("-Cforce-frame-pointers=yes"),
+            String::from("-Clink-arg=-no-pie"),
+            String::from("-Ztls-model=local-exec"),
+        ];
+        for (key, value) in self.get_configs() {
+            rustflags.push(format!("--cfg={}=\"{}\"", key, value));
+        }
+        rustflags
+    }
+
+    /// Get the hash of the resolved config (target, build_std, rustflags and envs).
+    pub fn get_hash(&self) -> String {
+        let mut content = format!("target={}\n", self.target);
+        if let Some(ref build_std) = self.build_std {
+            content += &format!("build_std={}\n", build_std);
+        }
+        content += &format!("rustflags={}\n", self.get_rustflags().join(" "));
+        for (key, value) in self.get_envs() {
+            content += &format!("env.{}={}\n", key, value);
+        }
+        format!("{:016x}", fnv1a_hash(content.as_bytes()))
+    }
+
+    /// Get the cargo target directory of this bin.
+    ///
+    /// Every config hash uses its own directory under `target/kbuild`, so
+    /// switching between bins reuses the artifacts built before.
+    pub fn get_target_dir(&self) -> PathBuf {
+        kbuild_dir().join(format!("{}-{}", self.name, self.get_hash()))
+    }
+
     pub fn get_meta(&self) -> HashMap<String, String> {
         let mut meta = HashMap::new();
         meta.insert(String::from("target"), self.target.clone());
+        meta.insert(String::from("hash"), self.get_hash());
+        meta.insert(
+            String::from("target_dir"),
+            self.get_target_dir().display().to_string(),
+        );
         meta
     }
 }
 
+/// The directory kbuild stores its artifacts and states, `target/kbuild` by default.
+pub fn kbuild_dir() -> PathBuf {
+    env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or(PathBuf::from("target"))
+        .join("kbuild")
+}
+
+/// 64-bit FNV-1a, stable between rust versions unlike `DefaultHasher`.
+fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl PatchConfig {
+    pub fn get_dir(&self) -> &str {
+        self.dir.as_deref().unwrap_or("crates")
+    }
+}
+
 impl KernelConfig {
     pub fn get_bin_config(&self, bin: &str) -> Result<BinaryConfig> {
         self.bin
             .get(bin)
-            .ok_or(anyhow!("can't find binary_config"))
+            .ok_or_else(|| {
+                let mut available: Vec<String> = self.bin.keys().cloned().collect();
+                available.sort();
+                anyhow!(KbuildError::UnknownBin {
+                    bin: bin.to_string(),
+                    available,
+                })
+            })
             .map(|x| {
                 let mut config = x.clone();
+                config.name = bin.to_string();
                 config.global_config = self.global.clone();
                 config
             })
     }
 }
 
-pub fn read_bin_config(path: &str, bin: &str) -> Result<BinaryConfig> {
-    let os_config = if path.ends_with(".yml") || path.ends_with(".yaml") {
+/// Read config from toml or yaml, depends on the extension.
+pub fn read_config(path: &str) -> Result<KernelConfig> {
+    if path.ends_with(".yml") || path.ends_with(".yaml") {
         read_yaml(path)
     } else {
         read_toml(path)
-    }?;
-    os_config.get_bin_config(bin)
+    }
+}
+
+pub fn read_bin_config(path: &str, bin: &str) -> Result<BinaryConfig> {
+    read_config(path)?.get_bin_config(bin)
+}
+
+/// Convert the error while reading the config to [KbuildError::Config].
+fn config_error(path: &str, reason: impl ToString) -> KbuildError {
+    KbuildError::Config {
+        path: path.to_string(),
+        reason: reason.to_string(),
+    }
 }
 
 /// Read config from toml
 pub fn read_toml(path: &str) -> Result<KernelConfig> {
-    let fcontent = fs::read_to_string(path)?;
-    let kernel_config: KernelConfig = toml::from_str(&fcontent)?;
+    let fcontent = fs::read_to_string(path).map_err(|err| config_error(path, err))?;
+    let kernel_config: KernelConfig =
+        toml::from_str(&fcontent).map_err(|err| config_error(path, err))?;
     Ok(kernel_config)
 }
 
 /// Read config from yaml
 pub fn read_yaml(path: &str) -> Result<KernelConfig> {
-    let fcontent = fs::read_to_string(path)?;
-    let kernel_config: KernelConfig = serde_yaml::from_str(&fcontent)?;
+    let fcontent = fs::read_to_string(path).map_err(|err| config_error(path, err))?;
+    let kernel_config: KernelConfig =
+        serde_yaml::from_str(&fcontent).map_err(|err| config_error(path, err))?;
     Ok(kernel_config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_hash_is_stable() {
+        // The hashes name the target directories, they must never change.
+        assert_eq!(fnv1a_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a_hash(b"foobar"), 0x85944171f73967e8);
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use color_print::cprintln;
+use json::JsonValue;
+
+/// A compiler error or warning from the cargo json messages.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: String,
+    /// The name of the crate target which emits the message.
+    pub krate: String,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub rendered: String,
+}
+
+/// The diagnostics collected from `cargo build --message-format=json`.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    list: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    /// Parse the `compiler-message` from cargo.
+    ///
+    /// Only errors and warnings are returned, the summary like
+    /// "aborting due to 2 previous errors" is skipped.
+    fn parse(msg: &JsonValue) -> Option<Self> {
+        let message = &msg["message"];
+        let level = message["level"].as_str()?;
+        if level != "error" && level != "warning" {
+            return None;
+        }
+        let text = message["message"].as_str().unwrap_or_default();
+        if message["spans"].is_empty()
+            && (text.starts_with("aborting due to") || text.ends_with("emitted"))
+        {
+            return None;
+        }
+        let span = message["spans"]
+            .members()
+            .find(|span| span["is_primary"].as_bool() == Some(true));
+        Some(Diagnostic {
+            level: level.to_string(),
+            krate: msg["target"]["name"]
+                .as_str()
+                .unwrap_or("unknown")
+                .to_string(),
+            message: text.to_string(),
+            file: span.and_then(|x| x["file_name"].as_str().map(String::from)),
+            line: span.and_then(|x| x["line_start"].as_usize()),
+            column: span.and_then(|x| x["column_start"].as_usize()),
+            rendered: message["rendered"].as_str().unwrap_or(text).to_string(),
+        })
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        json::object! {
+            level: self.level.clone(),
+            crate: self.krate.clone(),
+            message: self.message.clone(),
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            rendered: self.rendered.clone(),
+        }
+    }
+}
+
+impl Diagnostics {
+    /// Handle a line of the cargo json output.
+    ///
+    /// The json value is returned, so the caller can handle other messages.
+    /// If `quiet` is false, the rendered diagnostic will be printed like cargo does.
+    pub fn handle_line(&mut self, line: &str, quiet: bool) -> Option<JsonValue> {
+        let msg = json::parse(line).ok()?;
+        if msg["reason"] == "compiler-message" {
+            if let Some(diagnostic) = Diagnostic::parse(&msg) {
+                if !quiet {
+                    eprint!("{}", diagnostic.rendered);
+                }
+                self.list.push(diagnostic);
+            }
+        }
+        Some(msg)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.list.iter().filter(|x| x.level == "error")
+    }
+
+    /// Print the counts of errors and warnings per crate and the first error.
+    pub fn print_summary(&self) {
+        if self.list.is_empty() {
+            return;
+        }
+        // crate -> (errors, warnings)
+        let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+        for diagnostic in &self.list {
+            let count = counts.entry(&diagnostic.krate).or_default();
+            match diagnostic.level.as_str() {
+                "error" => count.0 += 1,
+                _ => count.1 += 1,
+            }
+        }
+        println!();
+        cprintln!("<bold>Diagnostics summary</bold>");
+        for (krate, (errors, warnings)) in counts {
+            cprintln!(
+                "    <green><bold>{:20}</bold></green> <red>{} errors</red>, <yellow>{} warnings</yellow>",
+                krate,
+                errors,
+                warnings
+            );
+        }
+        if let Some(first) = self.errors().next() {
+            println!();
+            cprintln!("<red><bold>First error</bold></red> in {}", first.krate);
+            eprint!("{}", first.rendered);
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::Array(self.list.iter().map(Diagnostic::to_json).collect())
+    }
+}
@@ -0,0 +1,176 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::Result;
+
+/// Read the unsigned integer from the elf file.
+fn read_uint(data: &[u8], offset: usize, size: usize, little_endian: bool) -> Result<u64> {
+    let bytes = offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+        .ok_or(anyhow!("elf file is truncated"))?;
+    let fold = |value: u64, byte: &u8| (value << 8) | *byte as u64;
+    Ok(match little_endian {
+        true => bytes.iter().rev().fold(0, fold),
+        false => bytes.iter().fold(0, fold),
+    })
+}
+
+/// Get the size of every section in the elf file.
+///
+/// Both 32-bit and 64-bit elf files of any endian are supported.
+pub fn section_sizes(path: &Path) -> Result<BTreeMap<String, u64>> {
+    parse_sections(&fs::read(path)?).map_err(|err| anyhow!("{err} in {}", path.display()))
+}
+
+/// Get the size of every section in the content of the elf file.
+fn parse_sections(data: &[u8]) -> Result<BTreeMap<String, u64>> {
+    if !data.starts_with(b"\x7fELF") || data.len() < 0x34 {
+        return Err(anyhow!("not a elf file"));
+    }
+    let is_64 = data[4] == 2;
+    let le = data[5] == 1;
+    let read = |offset: usize, size: usize| read_uint(data, offset, size, le);
+
+    // (e_shoff, e_shentsize, e_shnum, e_shstrndx, sh_size offset, word size)
+    let (shoff, shentsize, shnum, shstrndx, size_offset, word) = match is_64 {
+        true => (
+            read(0x28, 8)?,
+            read(0x3a, 2)?,
+            read(0x3c, 2)?,
+            read(0x3e, 2)?,
+            0x20,
+            8,
+        ),
+        false => (
+            read(0x20, 4)?,
+            read(0x2e, 2)?,
+            read(0x30, 2)?,
+            read(0x32, 2)?,
+            0x14,
+            4,
+        ),
+    };
+    // The values are read from the file, a malformed file must not overflow.
+    let header = |index: u64| {
+        index
+            .checked_mul(shentsize)
+            .and_then(|x| x.checked_add(shoff))
+            .and_then(|x| usize::try_from(x).ok())
+            .filter(|x| *x < data.len())
+            .ok_or(anyhow!("invalid section header"))
+    };
+    // sh_offset is located before sh_size.
+    let strtab = read(header(shstrndx)? + size_offset - word, word)?;
+
+    let mut sections = BTreeMap::new();
+    for index in 0..shnum {
+        let name_offset = strtab
+            .checked_add(read(header(index)?, 4)?)
+            .and_then(|x| usize::try_from(x).ok());
+        let name = name_offset
+            .and_then(|x| data.get(x..))
+            .and_then(|x| x.split(|c| *c == 0).next())
+            .ok_or(anyhow!("invalid section name"))?;
+        let size = read(header(index)? + size_offset, word)?;
+        if !name.is_empty() {
+            *sections
+                .entry(String::from_utf8_lossy(name).to_string())
+                .or_default() += size;
+        }
+    }
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build an elf file with the sections and the `.shstrtab`.
+    fn build_elf(is_64: bool, le: bool, sections: &[(&str, u64)]) -> Vec<u8> {
+        let (ehsize, shentsize, word, size_offset) = match is_64 {
+            true => (0x40, 0x40, 8, 0x20),
+            false => (0x34, 0x28, 4, 0x14),
+        };
+        let mut strtab = vec![0];
+        let mut names = Vec::new();
+        for (name, _) in sections.iter().chain([&(".shstrtab", 0)]) {
+            names.push(strtab.len() as u64);
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+        }
+        let shoff = ehsize + strtab.len();
+        let shnum = sections.len() + 2;
+        let mut data = vec![0; shoff + shnum * shentsize];
+        let mut write = |offset: usize, size: usize, value: u64| {
+            let bytes = match le {
+                true => value.to_le_bytes()[..size].to_vec(),
+                false => value.to_be_bytes()[8 - size..].to_vec(),
+            };
+            data[offset..offset + size].copy_from_slice(&bytes);
+        };
+        let header = match is_64 {
+            true => [(0x28, 8), (0x3a, 2), (0x3c, 2), (0x3e, 2)],
+            false => [(0x20, 4), (0x2e, 2), (0x30, 2), (0x32, 2)],
+        };
+        let values = [shoff, shentsize, shnum, shnum - 1];
+        for ((offset, size), value) in header.into_iter().zip(values) {
+            write(offset, size, value as u64);
+        }
+        let sizes = sections.iter().map(|(_, size)| *size);
+        for (index, size) in sizes.chain([strtab.len() as u64]).enumerate() {
+            let header = shoff + (index + 1) * shentsize;
+            write(header, 4, names[index]);
+            write(header + size_offset - word, word, ehsize as u64);
+            write(header + size_offset, word, size);
+        }
+        data[..6].copy_from_slice(&[0x7f, b'E', b'L', b'F', 1 + is_64 as u8, 2 - le as u8]);
+        data[ehsize..shoff].copy_from_slice(&strtab);
+        data
+    }
+
+    #[test]
+    fn parse_every_class_and_endian() {
+        let sections = [(".text", 0x1234), (".bss", 0x10), (".rodata", 7)];
+        for (is_64, le) in [(true, true), (true, false), (false, true), (false, false)] {
+            let sizes = parse_sections(&build_elf(is_64, le, &sections)).unwrap();
+            assert_eq!(sizes[".text"], 0x1234);
+            assert_eq!(sizes[".bss"], 0x10);
+            assert_eq!(sizes[".rodata"], 7);
+            assert_eq!(sizes.len(), 4);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn parse_the_test_binary() {
+        let sizes = section_sizes(&std::env::current_exe().unwrap()).unwrap();
+        assert!(sizes[".text"] > 0);
+    }
+
+    #[test]
+    fn sum_the_sections_with_the_same_name() {
+        let sizes = parse_sections(&build_elf(true, true, &[(".data", 3), (".data", 4)])).unwrap();
+        assert_eq!(sizes[".data"], 7);
+    }
+
+    #[test]
+    fn reject_malformed_files() {
+        assert!(parse_sections(b"not an elf file at all, but long enough to be one").is_err());
+        assert!(parse_sections(b"\x7fELF").is_err());
+
+        // The fields after sh_size in the last section header aren't read.
+        let elf = build_elf(true, true, &[(".text", 1)]);
+        for len in 0..elf.len() - 0x18 {
+            assert!(parse_sections(&elf[..len]).is_err());
+        }
+        // The section headers point outside of the file.
+        for offset in [0x28, 0x3a, 0x3e] {
+            let mut elf = elf.clone();
+            elf[offset..offset + 2].copy_from_slice(&[0xff, 0xff]);
+            assert!(parse_sections(&elf).is_err());
+        }
+        let mut elf = elf.clone();
+        elf[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(parse_sections(&elf).is_err());
+    }
+}
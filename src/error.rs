@@ -0,0 +1,100 @@
+use std::fmt::{self, Display};
+
+/// The error categories of kbuild.
+///
+/// Every category has its own exit code, so scripts can react to the failure:
+///
+/// | code | category                     |
+/// |------|------------------------------|
+/// | 1    | other errors                 |
+/// | 2    | config file can't be loaded  |
+/// | 3    | unknown bin                  |
+/// | 4    | external tool missing        |
+/// | 5    | build failed                 |
+/// | 6    | git command failed           |
+/// | 7    | patched crate is dirty       |
+#[derive(Debug)]
+pub enum KbuildError {
+    /// The config file can't be read or parsed.
+    Config { path: String, reason: String },
+    /// The bin can't be found in the config file.
+    UnknownBin { bin: String, available: Vec<String> },
+    /// The external tool (cargo, git, rustc) can't be found.
+    ToolMissing { tool: String },
+    /// Cargo exits with an error while building the bin, `diagnostics` is true
+    /// if the diagnostics are printed already.
+    BuildFailed {
+        bin: String,
+        status: String,
+        diagnostics: bool,
+    },
+    /// The git command exits with an error.
+    GitFailed { command: String, status: String },
+    /// The patched crate has files not committed.
+    DirtyPatch { name: String, files: String },
+}
+
+/// The exit code of the errors which are not [KbuildError].
+pub const OTHER_EXIT_CODE: i32 = 1;
+
+impl KbuildError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            KbuildError::Config { .. } => 2,
+            KbuildError::UnknownBin { .. } => 3,
+            KbuildError::ToolMissing { .. } => 4,
+            KbuildError::BuildFailed { .. } => 5,
+            KbuildError::GitFailed { .. } => 6,
+            KbuildError::DirtyPatch { .. } => 7,
+        }
+    }
+
+    /// Get the hint to fix the error.
+    pub fn hint(&self) -> Option<String> {
+        let hint = match self {
+            KbuildError::Config { .. } => String::from(
+                "check the path and the syntax of the config file, see example/default.toml",
+            ),
+            KbuildError::UnknownBin { available, .. } => {
+                format!("available bins: {}", available.join(", "))
+            }
+            KbuildError::ToolMissing { tool } => format!("install {tool} and add it to PATH"),
+            KbuildError::BuildFailed {
+                diagnostics: true, ..
+            } => return None,
+            KbuildError::BuildFailed { .. } => {
+                String::from("run with --diagnostics to get a summary of the errors")
+            }
+            KbuildError::GitFailed { .. } => {
+                String::from("check the network and the permission of the git repository")
+            }
+            KbuildError::DirtyPatch { .. } => {
+                String::from("commit or discard the changes in the patched crate first")
+            }
+        };
+        Some(hint)
+    }
+}
+
+impl Display for KbuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KbuildError::Config { path, reason } => {
+                write!(f, "can't load config file {path}: {reason}")
+            }
+            KbuildError::UnknownBin { bin, .. } => write!(f, "can't find bin {bin} in config"),
+            KbuildError::ToolMissing { tool } => write!(f, "can't find {tool}"),
+            KbuildError::BuildFailed { bin, status, .. } => {
+                write!(f, "build bin target {bin} failed, {status}")
+            }
+            KbuildError::GitFailed { command, status } => {
+                write!(f, "git {command} failed, {status}")
+            }
+            KbuildError::DirtyPatch { name, files } => {
+                write!(f, "crate {name} has some files unhandled\n{files}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KbuildError {}
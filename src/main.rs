@@ -1,7 +1,8 @@
 use std::{env, process::exit};
 
 use anyhow::Result;
-use color_print::cprintln;
+use color_print::cformat;
+use error::{KbuildError, OTHER_EXIT_CODE};
 
 #[macro_use]
 extern crate version;
@@ -10,6 +11,10 @@ extern crate anyhow;
 
 mod commands;
 mod config;
+mod diagnostics;
+mod elf;
+mod error;
+mod report;
 mod utils;
 
 /// To declare the command struct.
@@ -41,8 +46,17 @@ inventory::collect!(CommandAndHandler);
 
 fn main() {
     if let Err(err) = exec() {
-        cprintln!("<red><bold>Error: {}</bold></red>", err);
-        exit(100);
+        // The errors go to stderr, so the json diagnostics on stdout stay valid.
+        eprintln!("{}", cformat!("<red><bold>Error: {}</bold></red>", err));
+        match err.downcast_ref::<KbuildError>() {
+            Some(err) => {
+                if let Some(hint) = err.hint() {
+                    eprintln!("{}", cformat!("<yellow>hint: {}</yellow>", hint));
+                }
+                exit(err.exit_code());
+            }
+            None => exit(OTHER_EXIT_CODE),
+        }
     }
 }
 
@@ -0,0 +1,138 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::Result;
+use color_print::cprintln;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::kbuild_dir, elf::section_sizes};
+
+/// The sections shown in the report.
+const REPORT_SECTIONS: [&str; 4] = [".text", ".rodata", ".data", ".bss"];
+
+/// The report of a build, the last one of every bin is saved in `target/kbuild`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildReport {
+    pub hash: String,
+    /// Total build time in seconds.
+    pub build_time: f64,
+    pub executable: Option<String>,
+    #[serde(default)]
+    pub sections: BTreeMap<String, u64>,
+}
+
+impl BuildReport {
+    pub fn new(hash: String, build_time: f64, executable: Option<&Path>) -> Result<Self> {
+        let mut sections = BTreeMap::new();
+        if let Some(executable) = executable {
+            for (name, size) in section_sizes(executable)? {
+                if REPORT_SECTIONS.contains(&name.as_str()) {
+                    sections.insert(name, size);
+                }
+            }
+        }
+        Ok(BuildReport {
+            hash,
+            build_time,
+            executable: executable.map(|x| x.display().to_string()),
+            sections,
+        })
+    }
+
+    /// Load the report of the previous build.
+    pub fn load(bin: &str) -> Option<Self> {
+        let content = fs::read_to_string(kbuild_dir().join(format!("{bin}.report.toml"))).ok()?;
+        toml::from_str(&content).ok()
+    }
+
+    pub fn save(&self, bin: &str) -> Result<()> {
+        fs::create_dir_all(kbuild_dir())?;
+        fs::write(
+            kbuild_dir().join(format!("{bin}.report.toml")),
+            toml::to_string(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Print the report, compare with the previous build if exists.
+    pub fn print(&self, bin: &str, previous: Option<&BuildReport>, timings: &[(String, f64)]) {
+        println!();
+        cprintln!("<bold>Build report of {}</bold>", bin);
+        let time_diff = diff(previous.map(|x| x.build_time), self.build_time, 2, "s");
+        cprintln!(
+            "    <green><bold>{:20}</bold></green> {:>12.2}s {}",
+            "build time",
+            self.build_time,
+            time_diff
+        );
+        if !timings.is_empty() {
+            println!();
+            cprintln!("<bold>Slowest crates</bold>");
+            for (name, duration) in timings.iter().take(10) {
+                cprintln!(
+                    "    <green><bold>{:20}</bold></green> {:>12.2}s",
+                    name,
+                    duration
+                );
+            }
+        }
+        if let Some(ref executable) = self.executable {
+            println!();
+            cprintln!("<bold>Section sizes of {}</bold>", executable);
+            for name in REPORT_SECTIONS {
+                let Some(size) = self.sections.get(name) else {
+                    continue;
+                };
+                let old = previous.and_then(|x| x.sections.get(name).map(|x| *x as f64));
+                let size_diff = diff(old, *size as f64, 0, "");
+                cprintln!(
+                    "    <green><bold>{:20}</bold></green> {:>12} {}",
+                    name,
+                    size,
+                    size_diff
+                );
+            }
+        }
+    }
+}
+
+/// Format the difference between the previous value and the current value.
+fn diff(old: Option<f64>, new: f64, precision: usize, unit: &str) -> String {
+    match old {
+        Some(old) if new > old => {
+            color_print::cformat!("<red>(+{:.*}{})</red>", precision, new - old, unit)
+        }
+        Some(old) if new < old => {
+            color_print::cformat!("<green>(-{:.*}{})</green>", precision, old - new, unit)
+        }
+        _ => String::new(),
+    }
+}
+
+/// Get the build time of every crate from the cargo timing report, sorted by the duration.
+///
+/// Cargo doesn't provide the timings as json in stable, so the `UNIT_DATA`
+/// embedded in `cargo-timing.html` is parsed.
+pub fn crate_timings(target_dir: &Path) -> Result<Vec<(String, f64)>> {
+    let html = fs::read_to_string(target_dir.join("cargo-timings/cargo-timing.html"))?;
+    let start = html
+        .find("const UNIT_DATA = ")
+        .ok_or(anyhow!("can't find the unit data in the timing report"))?
+        + "const UNIT_DATA = ".len();
+    let end = start
+        + html[start..]
+            .find("\n];")
+            .ok_or(anyhow!("can't find the unit data in the timing report"))?
+        + "\n]".len();
+    let units = json::parse(&html[start..end])?;
+
+    let mut timings: BTreeMap<String, f64> = BTreeMap::new();
+    for unit in units.members() {
+        if let (Some(name), Some(duration)) = (unit["name"].as_str(), unit["duration"].as_f64()) {
+            *timings.entry(name.to_string()).or_default() += duration;
+        }
+    }
+    // Fresh crates are listed with zero duration.
+    let mut timings: Vec<_> = timings.into_iter().filter(|x| x.1 > 0.0).collect();
+    timings.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok(timings)
+}
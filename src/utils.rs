@@ -1,9 +1,19 @@
-use std::io::{stdin, stdout, Write};
+use std::{
+    ffi::OsStr,
+    io::{stdin, stdout, ErrorKind, Write},
+    process::{Child, Command},
+};
 
-#[allow(dead_code)]
+use anyhow::Result;
+use color_print::cprintln;
+use similar::TextDiff;
+
+use crate::error::KbuildError;
+
+/// Ask the user to confirm, the empty answer is `default`.
 pub fn confirm(tip: &str, default: bool) -> bool {
     let end = if default { " [Y/n]: " } else { " [y/N]: " };
-    print!("{tip} {end}");
+    print!("{tip}{end}");
     stdout().flush().expect("can't flush stdout");
     let mut ans = String::new();
     stdin().read_line(&mut ans).expect("can't read line");
@@ -11,6 +21,164 @@ pub fn confirm(tip: &str, default: bool) -> bool {
     if default {
         ans.trim().to_lowercase() != "n"
     } else {
-        ans.trim().to_lowercase() != "y"
+        ans.trim().to_lowercase() == "y"
+    }
+}
+
+/// Check if the flag (e.g. `--https`) was passed.
+pub fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
+}
+
+/// Get the value of the option, both `--name value` and `--name=value` are supported.
+pub fn get_option(args: &[String], name: &str) -> Option<String> {
+    let prefix = format!("{name}=");
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == name {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(&prefix).map(String::from)
+        }
+    })
+}
+
+/// Get the arguments without flags and options.
+///
+/// `options` are the options that take a value, their values will be skipped too.
+/// The program name and the command are kept, so the index of the arguments don't change.
+pub fn get_positional(args: &[String], options: &[&str]) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            res.push(arg.clone());
+        } else if options.contains(&arg.as_str()) {
+            iter.next();
+        }
+    }
+    res
+}
+
+/// Spawn the command, a missing program is reported as [KbuildError::ToolMissing].
+pub fn spawn(command: &mut Command) -> Result<Child> {
+    // The missing working directory is reported as NotFound too.
+    if let Some(dir) = command.get_current_dir() {
+        if !dir.is_dir() {
+            return Err(anyhow!("{} doesn't exist", dir.display()));
+        }
+    }
+    command.spawn().map_err(|err| match err.kind() {
+        ErrorKind::NotFound => KbuildError::ToolMissing {
+            tool: command.get_program().to_string_lossy().to_string(),
+        }
+        .into(),
+        _ => err.into(),
+    })
+}
+
+/// Match the text with the pattern, `*` matches any characters and `?` matches one.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    // The position of the last `*` and the text position it matched to.
+    let (mut star, mut matched) = (None, 0);
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some(p);
+            matched = t;
+            p += 1;
+        } else if let Some(star) = star {
+            p = star + 1;
+            matched += 1;
+            t = matched;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Quote the argument for the shell if it has special characters.
+fn shell_quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    match !arg.is_empty() && arg.chars().all(safe) {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', "'\\''")),
+    }
+}
+
+/// Format the command as a shell command line, the directory and the
+/// environment variables set on the command are included.
+pub fn format_command(command: &Command) -> String {
+    let mut parts = Vec::new();
+    if let Some(dir) = command.get_current_dir() {
+        parts.push(format!("cd {} &&", shell_quote(dir.as_os_str())));
+    }
+    for (key, value) in command.get_envs() {
+        if let Some(value) = value {
+            parts.push(format!("{}={}", key.to_string_lossy(), shell_quote(value)));
+        }
+    }
+    parts.push(shell_quote(command.get_program()));
+    parts.extend(command.get_args().map(shell_quote));
+    parts.join(" ")
+}
+
+/// Print the unified diff of the file.
+pub fn print_diff(name: &str, old: &str, new: &str) {
+    let diff = TextDiff::from_lines(old, new);
+    let unified = diff
+        .unified_diff()
+        .header(&format!("a/{name}"), &format!("b/{name}"))
+        .to_string();
+    for line in unified.lines() {
+        match line.chars().next() {
+            Some('+') => cprintln!("<green>{}</green>", line),
+            Some('-') => cprintln!("<red>{}</red>", line),
+            Some('@') => cprintln!("<cyan>{}</cyan>", line),
+            _ => println!("{line}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn match_wildcards() {
+        assert!(wildcard_match("polyhal*", "polyhal-boot"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a?c", "abc"));
+        assert!(wildcard_match("*-boot", "polyhal-boot"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("a?c", "ac"));
+        assert!(!wildcard_match("polyhal", "polyhal-boot"));
+        assert!(!wildcard_match("*-boot", "polyhal"));
+        assert!(!wildcard_match("", "a"));
+    }
+
+    #[test]
+    fn parse_the_arguments() {
+        let args = args(&["kbuild", "patch", "add", "--rev", "dev", "log", "--https"]);
+        assert!(has_flag(&args, "--https"));
+        assert!(!has_flag(&args, "--all"));
+        assert_eq!(get_option(&args, "--rev").as_deref(), Some("dev"));
+        assert_eq!(get_option(&args, "--from"), None);
+        let jobs = self::args(&["kbuild", "patch", "patch_all", "--jobs=8"]);
+        assert_eq!(get_option(&jobs, "--jobs").as_deref(), Some("8"));
+        assert_eq!(
+            get_positional(&args, &["--rev"]),
+            ["kbuild", "patch", "add", "log"]
+        );
     }
 }